@@ -0,0 +1,56 @@
+use advent_of_code_2018::automaton::Rule1D;
+use advent_of_code_2018::bitvec_set::BitVecRow;
+use ahash::AHashSet;
+use criterion::{Criterion, criterion_group, criterion_main};
+use std::hint::black_box;
+
+const GENERATIONS: usize = 500;
+
+/// The day 12 loop before plants were bit-packed: five set lookups per pot.
+fn step_hash_set(rule: &Rule1D, row: &AHashSet<i64>) -> AHashSet<i64> {
+    let (Some(&min), Some(&max)) = (row.iter().min(), row.iter().max()) else {
+        return AHashSet::default();
+    };
+    (min - 2..=max + 2)
+        .filter(|p| {
+            let window =
+                (p - 2..=p + 2).fold(0, |acc, q| (acc << 1) | usize::from(row.contains(&q)));
+            rule.table()[window]
+        })
+        .collect()
+}
+
+fn bench_rows(c: &mut Criterion) {
+    let mut rng = fastrand::Rng::with_seed(12);
+    let mut table: [bool; 32] = std::array::from_fn(|_| rng.bool());
+    table[0] = false;
+    let rule = Rule1D::new(table);
+    let start = (0..2000).filter(|_| rng.bool()).collect::<Vec<i64>>();
+
+    let hash_set = start.iter().copied().collect::<AHashSet<_>>();
+    let bit_row = start.iter().copied().collect::<BitVecRow>();
+    let by_set = (0..GENERATIONS).fold(hash_set.clone(), |row, _| step_hash_set(&rule, &row));
+    let by_row = (0..GENERATIONS).fold(bit_row.clone(), |row, _| rule.next(&row));
+    assert_eq!(
+        by_set.iter().sum::<i64>(),
+        by_row.iter().sum::<i64>(),
+        "both steppers must agree"
+    );
+
+    let mut group = c.benchmark_group("500 generations of a 2000-pot row");
+    group.sample_size(20);
+    group.bench_function("AHashSet", |b| {
+        b.iter(|| {
+            (0..GENERATIONS).fold(black_box(hash_set.clone()), |row, _| {
+                step_hash_set(&rule, &row)
+            })
+        })
+    });
+    group.bench_function("BitVecRow", |b| {
+        b.iter(|| (0..GENERATIONS).fold(black_box(bit_row.clone()), |row, _| rule.next(&row)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_rows);
+criterion_main!(benches);
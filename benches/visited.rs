@@ -0,0 +1,114 @@
+use advent_of_code_2018::bitvec_set::BitVecSet3D;
+use ahash::AHashSet;
+use criterion::{Criterion, criterion_group, criterion_main};
+use ndarray::{Array2, Array3};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::hint::black_box;
+
+const TORCH: usize = 0;
+
+/// Tall, narrow day 22 caves, as (depth, tx, ty).
+const CAVES: [(usize, usize, usize); 3] = [(4848, 15, 700), (11739, 11, 718), (7863, 14, 760)];
+const PADDING: usize = 50;
+
+fn erosion_levels(depth: usize, tx: usize, ty: usize) -> Array2<usize> {
+    let (height, width) = (ty + PADDING, tx + PADDING);
+    let mut erosion = Array2::zeros((height, width));
+    for y in 0..height {
+        for x in 0..width {
+            let index = if (x, y) == (0, 0) || (x, y) == (tx, ty) {
+                0
+            } else if y == 0 {
+                x * 16807
+            } else if x == 0 {
+                y * 48271
+            } else {
+                erosion[(y - 1, x)] * erosion[(y, x - 1)]
+            };
+            erosion[(y, x)] = (index + depth) % 20183;
+        }
+    }
+    erosion
+}
+
+/// A fixed copy of day 22's original Dijkstra over a padded grid, not the A*
+/// search the solver now uses, so that only the visited set varies. `visit`
+/// returns whether a (y, x, gear) state was newly visited.
+fn fastest(
+    erosion: &Array2<usize>,
+    (tx, ty): (usize, usize),
+    mut visit: impl FnMut([usize; 3]) -> bool,
+) -> usize {
+    let (height, width) = erosion.dim();
+    // Each region type rules out exactly one tool.
+    let allowed = |y: usize, x: usize, gear| (erosion[(y, x)] + 2) % 3 != gear;
+
+    let mut heap = BinaryHeap::from([Reverse((0, 0, 0, TORCH))]);
+    while let Some(Reverse((minutes, y, x, gear))) = heap.pop() {
+        if !visit([y, x, gear]) {
+            continue;
+        }
+        if (x, y, gear) == (tx, ty, TORCH) {
+            return minutes;
+        }
+        for tool in (0..3).filter(|&t| t != gear && allowed(y, x, t)) {
+            heap.push(Reverse((minutes + 7, y, x, tool)));
+        }
+        for (ny, nx) in [
+            (y.wrapping_sub(1), x),
+            (y + 1, x),
+            (y, x.wrapping_sub(1)),
+            (y, x + 1),
+        ] {
+            if ny < height && nx < width && allowed(ny, nx, gear) {
+                heap.push(Reverse((minutes + 1, ny, nx, gear)));
+            }
+        }
+    }
+    panic!("target unreachable")
+}
+
+fn bench_visited(c: &mut Criterion) {
+    let mut group = c.benchmark_group("day 22 visited states");
+    for (depth, tx, ty) in CAVES {
+        let erosion = erosion_levels(depth, tx, ty);
+        let dim = [erosion.dim().0, erosion.dim().1, 3];
+
+        let by_bitset = fastest(&erosion, (tx, ty), {
+            let mut visited = BitVecSet3D::new(dim);
+            move |state| visited.insert(state)
+        });
+        let by_hash_set = fastest(&erosion, (tx, ty), {
+            let mut visited = AHashSet::default();
+            move |state| visited.insert(state)
+        });
+        assert_eq!(by_bitset, by_hash_set, "both sets must give the same route");
+
+        let name = format!("depth {depth}, target {tx},{ty}");
+        group.bench_function(format!("{name}: BitVecSet3D"), |b| {
+            b.iter(|| {
+                let mut visited = BitVecSet3D::new(dim);
+                fastest(black_box(&erosion), (tx, ty), |state| visited.insert(state))
+            })
+        });
+        group.bench_function(format!("{name}: AHashSet"), |b| {
+            b.iter(|| {
+                let mut visited = AHashSet::default();
+                fastest(black_box(&erosion), (tx, ty), |state| visited.insert(state))
+            })
+        });
+        group.bench_function(format!("{name}: Array3<bool>"), |b| {
+            b.iter(|| {
+                let mut visited = Array3::from_elem(dim, false);
+                fastest(black_box(&erosion), (tx, ty), |[y, x, gear]| {
+                    !std::mem::replace(&mut visited[(y, x, gear)], true)
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_visited);
+criterion_main!(benches);
@@ -0,0 +1,48 @@
+set -e
+
+# Usage: ./compare_revisions.sh <day> <old revision> <input>...
+#
+# Builds one day's binary at an older revision alongside the working tree,
+# checks that both print the same answers for every input, then benchmarks
+# the pair on each input.
+
+HYPERFINE_RUN_ARGS="--warmup=5 --runs 25"
+
+DAY=$1
+OLD_REV=$2
+shift 2
+
+if test -z "$DAY" || test -z "$OLD_REV" || test $# -eq 0; then
+    echo "usage: $0 <day> <old revision> <input>..."
+    exit 1
+fi
+
+BIN="2018_$DAY"
+OLD_TREE="target/compare/$(git rev-parse --short "$OLD_REV")"
+
+if ! test -d "$OLD_TREE"; then
+    git worktree add --detach "$OLD_TREE" "$OLD_REV"
+fi
+# Cargo.lock isn't tracked, so build both trees against the same dependencies.
+if test -f Cargo.lock; then
+    cp Cargo.lock "$OLD_TREE/Cargo.lock"
+fi
+
+cargo build --release --bin "$BIN"
+cargo build --release --bin "$BIN" --manifest-path "$OLD_TREE/Cargo.toml" --target-dir target/compare/target
+
+OLD_CMD="./target/compare/target/release/$BIN"
+NEW_CMD="./target/release/$BIN"
+
+for INPUT in "$@"
+do
+    echo ""
+    echo "$BIN on $INPUT"
+    if test "$($OLD_CMD --input "$INPUT")" != "$($NEW_CMD --input "$INPUT")"; then
+        echo "answers differ between $OLD_REV and the working tree"
+        exit 1
+    fi
+    hyperfine $HYPERFINE_RUN_ARGS -N -u millisecond --style basic \
+        -n "$OLD_REV" "$OLD_CMD --input $INPUT" \
+        -n "working tree" "$NEW_CMD --input $INPUT"
+done;
@@ -0,0 +1,244 @@
+use crate::bitvec_set::BitVecRow;
+use ndarray::{Array2, Axis};
+use rayon::prelude::*;
+use std::ops::RangeInclusive;
+
+pub const MOORE: [(isize, isize); 8] = [
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+    (0, -1),
+    (0, 1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+];
+
+pub const VON_NEUMANN: [(isize, isize); 4] = [(-1, 0), (0, -1), (0, 1), (1, 0)];
+
+/// Decides the next value of a cell from its current value and the number of
+/// neighbours holding each of the automaton's tracked values.
+pub trait Rule<const N: usize>: Sync {
+    fn next(&self, cell: u8, counts: &[u8; N]) -> u8;
+}
+
+impl<const N: usize, F> Rule<N> for F
+where
+    F: Fn(u8, &[u8; N]) -> u8 + Sync,
+{
+    fn next(&self, cell: u8, counts: &[u8; N]) -> u8 {
+        self(cell, counts)
+    }
+}
+
+/// A cell holding `from` becomes `to` if every tracked neighbour count lies
+/// within the corresponding range.
+pub struct Transition<const N: usize> {
+    pub from: u8,
+    pub to: u8,
+    pub counts: [RangeInclusive<u8>; N],
+}
+
+/// A ruleset expressed as data. The first matching transition wins; cells
+/// with no matching transition are unchanged.
+pub struct RuleTable<const N: usize> {
+    pub transitions: &'static [Transition<N>],
+}
+
+impl<const N: usize> Rule<N> for RuleTable<N> {
+    fn next(&self, cell: u8, counts: &[u8; N]) -> u8 {
+        self.transitions
+            .iter()
+            .find(|t| t.from == cell && t.counts.iter().zip(counts).all(|(r, c)| r.contains(c)))
+            .map(|t| t.to)
+            .unwrap_or(cell)
+    }
+}
+
+/// A 2D cellular automaton over a byte grid. Cells outside the grid count as
+/// holding no tracked value.
+pub struct Automaton2D<const N: usize, R: Rule<N>> {
+    pub neighbourhood: &'static [(isize, isize)],
+    pub tracked: [u8; N],
+    pub rule: R,
+}
+
+impl<const N: usize, R: Rule<N>> Automaton2D<N, R> {
+    fn cell(&self, src: &Array2<u8>, y: usize, x: usize) -> u8 {
+        let mut counts = [0; N];
+        for &(dy, dx) in self.neighbourhood {
+            if let Some(v) = src.get((y.wrapping_add_signed(dy), x.wrapping_add_signed(dx))) {
+                for (count, t) in counts.iter_mut().zip(self.tracked) {
+                    if *v == t {
+                        *count += 1;
+                    }
+                }
+            }
+        }
+        self.rule.next(src[(y, x)], &counts)
+    }
+
+    pub fn step_into(&self, src: &Array2<u8>, dst: &mut Array2<u8>) {
+        debug_assert_eq!(src.dim(), dst.dim());
+        dst.indexed_iter_mut()
+            .for_each(|((y, x), e)| *e = self.cell(src, y, x));
+    }
+
+    pub fn par_step_into(&self, src: &Array2<u8>, dst: &mut Array2<u8>) {
+        debug_assert_eq!(src.dim(), dst.dim());
+        dst.axis_iter_mut(Axis(0))
+            .into_par_iter()
+            .enumerate()
+            .for_each(|(y, mut row)| {
+                row.iter_mut()
+                    .enumerate()
+                    .for_each(|(x, e)| *e = self.cell(src, y, x));
+            });
+    }
+
+    pub fn next(&self, src: &Array2<u8>) -> Array2<u8> {
+        let mut dst = Array2::zeros(src.dim());
+        self.step_into(src, &mut dst);
+        dst
+    }
+
+    /// Advances `grid` by `generations` steps, reusing a single scratch buffer.
+    pub fn run(&self, grid: &mut Array2<u8>, generations: usize) {
+        let mut buffer = Array2::zeros(grid.dim());
+        for _ in 0..generations {
+            self.step_into(grid, &mut buffer);
+            std::mem::swap(grid, &mut buffer);
+        }
+    }
+
+    /// As [`Automaton2D::run`], but computes each generation's rows in parallel.
+    pub fn par_run(&self, grid: &mut Array2<u8>, generations: usize) {
+        let mut buffer = Array2::zeros(grid.dim());
+        for _ in 0..generations {
+            self.par_step_into(grid, &mut buffer);
+            std::mem::swap(grid, &mut buffer);
+        }
+    }
+}
+
+/// A 1D rule over a cell and its two neighbours on either side. The table is
+/// indexed by the five cells read left to right as a binary number.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Rule1D {
+    table: [bool; 32],
+}
+
+impl Rule1D {
+    /// Panics if an empty neighbourhood would produce a live cell, since the
+    /// row is infinite and would fill up.
+    pub fn new(table: [bool; 32]) -> Rule1D {
+        assert!(!table[0], "rule would fill the infinite row");
+        Rule1D { table }
+    }
+
+    pub fn from_fn(f: impl Fn([bool; 5]) -> bool) -> Rule1D {
+        Rule1D::new(std::array::from_fn(|idx| {
+            f(std::array::from_fn(|i| idx & (16 >> i) != 0))
+        }))
+    }
+
+    pub fn table(&self) -> &[bool; 32] {
+        &self.table
+    }
+
+    /// Builds a rule from the neighbourhoods which produce a live cell.
+    pub fn from_patterns<'a>(patterns: impl IntoIterator<Item = &'a [bool; 5]>) -> Rule1D {
+        let mut table = [false; 32];
+        for pattern in patterns {
+            let idx = pattern
+                .iter()
+                .fold(0, |acc, &b| (acc << 1) | usize::from(b));
+            table[idx] = true;
+        }
+        Rule1D::new(table)
+    }
+
+    /// Computes the next generation of a row 64 cells at a time.
+    pub fn next(&self, row: &BitVecRow) -> BitVecRow {
+        let words = row.words();
+        let word = |k: isize| {
+            usize::try_from(k)
+                .ok()
+                .and_then(|k| words.get(k))
+                .copied()
+                .unwrap_or(0)
+        };
+        let masks = self.table.map(|live| if live { u64::MAX } else { 0 });
+
+        // One extra word each side leaves room for growth.
+        let next_words = (-1..=words.len() as isize)
+            .map(|k| {
+                let (prev, cur, next) = (word(k - 1), word(k), word(k + 1));
+                let inputs = [
+                    (cur >> 2) | (next << 62),
+                    (cur >> 1) | (next << 63),
+                    cur,
+                    (cur << 1) | (prev >> 63),
+                    (cur << 2) | (prev >> 62),
+                ];
+
+                let mut vals = masks;
+                let mut len = vals.len();
+                for sel in inputs {
+                    len /= 2;
+                    for i in 0..len {
+                        vals[i] = (sel & vals[2 * i + 1]) | (!sel & vals[2 * i]);
+                    }
+                }
+                vals[0]
+            })
+            .collect();
+
+        BitVecRow::from_words(next_words, row.offset - 64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_grid(seed: u64, height: usize, width: usize) -> Array2<u8> {
+        let mut rng = fastrand::Rng::with_seed(seed);
+        Array2::from_shape_fn((height, width), |_| rng.u8(..3))
+    }
+
+    #[test]
+    fn test_run_matches_par_run() {
+        let automaton = Automaton2D {
+            neighbourhood: &MOORE,
+            tracked: [1, 2],
+            rule: |cell: u8, counts: &[u8; 2]| match (cell, counts) {
+                (0, [3, _]) => 1,
+                (1, [2..=3, _]) => 1,
+                (1, _) => 2,
+                (2, [_, 0..=2]) => 0,
+                _ => cell,
+            },
+        };
+
+        for seed in 0..20 {
+            let start = random_grid(seed, 1 + seed as usize % 7, 1 + seed as usize * 3 % 40);
+            let mut expected = start.clone();
+            for generations in 0..20 {
+                let mut serial = start.clone();
+                let mut parallel = start.clone();
+                automaton.run(&mut serial, generations);
+                automaton.par_run(&mut parallel, generations);
+                assert_eq!(serial, expected, "seed {seed}, {generations} generations");
+                assert_eq!(parallel, expected, "seed {seed}, {generations} generations");
+                expected = automaton.next(&expected);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "rule would fill the infinite row")]
+    fn test_rule_filling_row() {
+        Rule1D::from_fn(|cells| cells == [false; 5]);
+    }
+}
@@ -1,6 +1,6 @@
 use advent_of_code_2018::{Cli, Parser};
 use itertools::Itertools;
-use rayon::prelude::*;
+use ndarray::Array2;
 use std::fs;
 
 fn parse(raw_inp: &str) -> Vec<(i32, i32)> {
@@ -12,72 +12,92 @@ fn parse(raw_inp: &str) -> Vec<(i32, i32)> {
         .collect()
 }
 
-fn closest(coord: (i32, i32), data: &[(i32, i32)]) -> Option<&(i32, i32)> {
-    let minset = data
+// Paths inside the box are Manhattan, so one search from all coordinates
+// labels each location with its closest, or None for a tie.
+fn voronoi(data: &[(i32, i32)]) -> Array2<Option<usize>> {
+    let (min_x, max_x) = data
         .iter()
-        .min_set_by_key(|pt| (coord.0 - pt.0).abs() + (coord.1 - pt.1).abs());
-
-    if minset.len() == 1 {
-        minset.first().copied()
-    } else {
-        None
+        .map(|c| c.0)
+        .minmax()
+        .into_option()
+        .expect("non-empty");
+    let (min_y, max_y) = data
+        .iter()
+        .map(|c| c.1)
+        .minmax()
+        .into_option()
+        .expect("non-empty");
+    let (height, width) = ((max_y - min_y + 1) as usize, (max_x - min_x + 1) as usize);
+
+    let mut owners = Array2::from_elem((height, width), None);
+    let mut dist = Array2::from_elem((height, width), usize::MAX);
+    let mut frontier = vec![];
+    for (i, &(x, y)) in data.iter().enumerate() {
+        let pos = ((y - min_y) as usize, (x - min_x) as usize);
+        if dist[pos] == 0 {
+            owners[pos] = None;
+        } else {
+            dist[pos] = 0;
+            owners[pos] = Some(i);
+            frontier.push(pos);
+        }
     }
-}
 
-fn is_finite(coord: &(i32, i32), data: &[(i32, i32)]) -> bool {
-    let mut other_data = data.to_vec();
-    other_data.retain(|c| c != coord);
+    for d in 1.. {
+        if frontier.is_empty() {
+            break;
+        }
+        let mut next = vec![];
+        for (y, x) in frontier {
+            let owner = owners[(y, x)];
+            let neighbours = [
+                (y.wrapping_sub(1), x),
+                (y + 1, x),
+                (y, x.wrapping_sub(1)),
+                (y, x + 1),
+            ];
+            for n in neighbours {
+                if n.0 >= height || n.1 >= width {
+                    continue;
+                }
+                if dist[n] == usize::MAX {
+                    dist[n] = d;
+                    owners[n] = owner;
+                    next.push(n);
+                } else if dist[n] == d && owners[n] != owner {
+                    owners[n] = None;
+                }
+            }
+        }
+        frontier = next;
+    }
 
-    other_data
-        .iter()
-        .any(|other| other.0 >= coord.0 + (coord.1 - other.1).abs())
-        && other_data
-            .iter()
-            .any(|other| other.0 <= coord.0 - (coord.1 - other.1).abs())
-        && other_data
-            .iter()
-            .any(|other| other.1 >= coord.1 + (coord.0 - other.0).abs())
-        && other_data
-            .iter()
-            .any(|other| other.1 <= coord.1 - (coord.0 - other.0).abs())
+    owners
 }
 
-fn region_size(coord: &(i32, i32), data: &[(i32, i32)]) -> i32 {
-    // Maximum distance away in each direction we can find a point that's closest to us
-    let min_xd = (1..)
-        .find_or_first(|&d| closest((coord.0 - d, coord.1), data) != Some(coord))
-        .expect("infinite")
-        - 1;
-    let max_xd = (1..)
-        .find_or_first(|&d| closest((coord.0 + d, coord.1), data) != Some(coord))
-        .expect("infinite")
-        - 1;
-    let min_yd = (1..)
-        .find_or_first(|&d| closest((coord.0, coord.1 - d), data) != Some(coord))
-        .expect("infinite")
-        - 1;
-    let max_yd = (1..)
-        .find_or_first(|&d| closest((coord.0, coord.1 + d), data) != Some(coord))
-        .expect("infinite")
-        - 1;
-
-    let mut ans = 0;
-    for y in coord.1 - min_yd..=coord.1 + max_yd {
-        for x in coord.0 - min_xd..=coord.0 + max_xd {
-            if closest((x, y), data) == Some(coord) {
-                ans += 1;
+// Regions reaching the edge of the bounding box are the infinite ones.
+fn calculate_p1(data: &[(i32, i32)]) -> usize {
+    let owners = voronoi(data);
+    let (height, width) = owners.dim();
+
+    let mut sizes = vec![0; data.len()];
+    let mut infinite = vec![false; data.len()];
+    for ((y, x), owner) in owners.indexed_iter() {
+        if let Some(i) = *owner {
+            sizes[i] += 1;
+            if y == 0 || x == 0 || y == height - 1 || x == width - 1 {
+                infinite[i] = true;
             }
         }
     }
-    ans
-}
 
-fn calculate_p1(data: &[(i32, i32)]) -> i32 {
-    data.par_iter()
-        .filter(|&coord| is_finite(coord, data))
-        .map(|coord| region_size(coord, data))
+    sizes
+        .into_iter()
+        .zip(infinite)
+        .filter(|&(_, infinite)| !infinite)
+        .map(|(size, _)| size)
         .max()
-        .expect("non-empty data")
+        .expect("no finite regions")
 }
 
 fn calculate_p2<const CUTOFF: i32>(data: &[(i32, i32)]) -> i32 {
@@ -141,6 +161,43 @@ mod tests {
         assert_eq!(calculate_p1(&parse(EXAMPLE_DATA)), 17);
     }
 
+    fn brute_force_sizes(data: &[(i32, i32)], margin: i32) -> Vec<usize> {
+        let (min_x, max_x) = data.iter().map(|c| c.0).minmax().into_option().unwrap();
+        let (min_y, max_y) = data.iter().map(|c| c.1).minmax().into_option().unwrap();
+
+        let mut sizes = vec![0; data.len()];
+        for y in min_y - margin..=max_y + margin {
+            for x in min_x - margin..=max_x + margin {
+                let closest = (0..data.len())
+                    .min_set_by_key(|&i| (data[i].0 - x).abs() + (data[i].1 - y).abs());
+                if let [i] = closest[..] {
+                    sizes[i] += 1;
+                }
+            }
+        }
+        sizes
+    }
+
+    #[test]
+    fn test_p1_matches_brute_force() {
+        let mut rng = fastrand::Rng::with_seed(6);
+        for _ in 0..200 {
+            let data = (0..rng.usize(2..12))
+                .map(|_| (rng.i32(0..15), rng.i32(0..15)))
+                .collect_vec();
+            // Infinite regions keep growing with the box, finite ones don't.
+            let expected = brute_force_sizes(&data, 1)
+                .into_iter()
+                .zip(brute_force_sizes(&data, 2))
+                .filter(|(a, b)| a == b)
+                .map(|(a, _)| a)
+                .max();
+            if let Some(expected) = expected {
+                assert_eq!(calculate_p1(&data), expected, "{data:?}");
+            }
+        }
+    }
+
     #[test]
     fn test_p2_example() {
         assert_eq!(calculate_p2::<32>(&parse(EXAMPLE_DATA)), 16);
@@ -1,9 +1,10 @@
+use advent_of_code_2018::automaton::Rule1D;
+use advent_of_code_2018::bitvec_set::BitVecRow;
+use advent_of_code_2018::hashlife::HashLife1D;
 use advent_of_code_2018::{Cli, Parser};
-use ahash::AHashSet;
-use itertools::Itertools;
 use std::fs;
 
-fn parse(raw_inp: &str) -> (AHashSet<i64>, AHashSet<[bool; 5]>) {
+fn parse(raw_inp: &str) -> (BitVecRow, Rule1D) {
     let (initial_state, rules) = raw_inp.split_once("\n\n").expect("bad format");
 
     let initial_state = initial_state
@@ -14,7 +15,7 @@ fn parse(raw_inp: &str) -> (AHashSet<i64>, AHashSet<[bool; 5]>) {
         .enumerate()
         .filter(|(_, itm)| *itm == b'#')
         .map(|(pos, _)| pos as i64)
-        .collect::<AHashSet<_>>();
+        .collect::<BitVecRow>();
 
     let rules = rules
         .trim()
@@ -29,51 +30,30 @@ fn parse(raw_inp: &str) -> (AHashSet<i64>, AHashSet<[bool; 5]>) {
                 .try_into()
                 .ok()
         })
-        .collect::<AHashSet<_>>();
+        .collect::<Vec<[bool; 5]>>();
 
-    (initial_state, rules)
+    (initial_state, Rule1D::from_patterns(&rules))
 }
 
-fn calculate<const GENERATIONS: i64>(data: &(AHashSet<i64>, AHashSet<[bool; 5]>)) -> i64 {
-    let mut next_generation = AHashSet::default();
-    let mut this_generation = data.0.clone();
+// Below this, stepping the row directly is cheaper than building up hashlife's
+// memoised blocks.
+const DIRECT_STEP_LIMIT: u64 = 1000;
 
-    for g in 0..GENERATIONS {
-        let (min, max) = this_generation
+fn calculate<const GENERATIONS: u64>(data: &(BitVecRow, Rule1D)) -> i64 {
+    if GENERATIONS <= DIRECT_STEP_LIMIT {
+        let (row, rule) = data;
+        return (0..GENERATIONS)
+            .fold(row.clone(), |row, _| rule.next(&row))
             .iter()
-            .minmax()
-            .into_option()
-            .expect("should have some plants");
-
-        next_generation.clear();
-
-        for p in *min - 2..=*max + 2 {
-            let (a, b, c, d, e) = (
-                this_generation.contains(&(p - 2)),
-                this_generation.contains(&(p - 1)),
-                this_generation.contains(&p),
-                this_generation.contains(&(p + 1)),
-                this_generation.contains(&(p + 2)),
-            );
-
-            if data.1.contains(&[a, b, c, d, e]) {
-                next_generation.insert(p);
-            }
-        }
-
-        if this_generation.len() == next_generation.len()
-            && next_generation
-                .iter()
-                .all(|n| this_generation.contains(&(n - 1)))
-        {
-            return this_generation.iter().sum::<i64>()
-                + (GENERATIONS - g) * this_generation.len() as i64;
-        }
-
-        std::mem::swap(&mut next_generation, &mut this_generation);
+            .sum();
     }
 
-    this_generation.iter().sum()
+    let mut hashlife = HashLife1D::new(&data.0, data.1);
+    hashlife.advance(GENERATIONS);
+    hashlife
+        .position_sum()
+        .try_into()
+        .expect("position sum overflowed i64")
 }
 
 fn main() {
@@ -99,6 +79,27 @@ mod tests {
         assert_eq!(calculate::<20>(&parse(EXAMPLE_DATA)), 325);
     }
 
+    #[test]
+    fn test_p2_example() {
+        assert_eq!(calculate::<50000000000>(&parse(EXAMPLE_DATA)), 999999999374);
+    }
+
+    #[test]
+    fn test_direct_steps_match_hashlife() {
+        let (row, rule) = parse(EXAMPLE_DATA);
+        let mut direct = row.clone();
+        let mut hashlife = HashLife1D::new(&row, rule);
+        for generation in 0..200 {
+            assert_eq!(
+                hashlife.position_sum(),
+                direct.iter().sum::<i64>() as i128,
+                "generation {generation}"
+            );
+            direct = rule.next(&direct);
+            hashlife.advance(1);
+        }
+    }
+
     #[test]
     fn test_p1_real() {
         assert_eq!(calculate::<20>(&parse(REAL_DATA)), 3051);
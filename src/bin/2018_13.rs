@@ -1,7 +1,8 @@
 use advent_of_code_2018::grid_util::make_byte_grid;
 use advent_of_code_2018::{Cli, Parser};
+use itertools::Itertools;
 use ndarray::Array2;
-use std::cell::RefCell;
+use std::fmt;
 use std::fs;
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy, PartialOrd, Ord)]
@@ -24,6 +25,19 @@ impl From<u8> for Direction {
     }
 }
 
+impl From<Direction> for u8 {
+    fn from(value: Direction) -> Self {
+        match value {
+            Direction::Down => b'v',
+            Direction::Up => b'^',
+            Direction::Right => b'>',
+            Direction::Left => b'<',
+        }
+    }
+}
+
+type CartId = usize;
+
 #[derive(PartialEq, Eq, Debug, Clone, Copy, PartialOrd, Ord)]
 struct Cart {
     y: usize,
@@ -34,7 +48,14 @@ struct Cart {
 }
 
 impl Cart {
+    // Turns after moving, so `dir` is the way the cart will leave.
     fn move_cart(&mut self, grid: &Array2<u8>) {
+        match self.dir {
+            Direction::Up => self.y -= 1,
+            Direction::Down => self.y += 1,
+            Direction::Right => self.x += 1,
+            Direction::Left => self.x -= 1,
+        }
         match grid.get((self.y, self.x)) {
             Some(b'/') => match self.dir {
                 Direction::Right => self.dir = Direction::Up,
@@ -49,7 +70,7 @@ impl Cart {
                 Direction::Up => self.dir = Direction::Left,
             },
             Some(b'+') => {
-                if self.intersection_counter % 3 == 0 {
+                if self.intersection_counter.is_multiple_of(3) {
                     match self.dir {
                         Direction::Up => self.dir = Direction::Left,
                         Direction::Right => self.dir = Direction::Up,
@@ -69,16 +90,132 @@ impl Cart {
             None => panic!("Cart out of bounds"),
             _ => {}
         }
-        match self.dir {
-            Direction::Up => self.y -= 1,
-            Direction::Down => self.y += 1,
-            Direction::Right => self.x += 1,
-            Direction::Left => self.x -= 1,
+    }
+}
+
+struct Track {
+    grid: Array2<u8>,
+}
+
+// Ticks are 1-based, and the cart which moved comes first.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+struct Collision {
+    tick: usize,
+    y: usize,
+    x: usize,
+    carts: [CartId; 2],
+}
+
+impl fmt::Display for Collision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tick {}: carts {} and {} crash at {},{}",
+            self.tick, self.carts[0], self.carts[1], self.x, self.y
+        )
+    }
+}
+
+#[derive(Clone)]
+struct CartSim<'a> {
+    track: &'a Track,
+    // Indexed by cart id, including carts which have crashed.
+    carts: Vec<Cart>,
+    order: Vec<CartId>,
+    occupancy: Array2<Option<CartId>>,
+    ticks: usize,
+    collisions: Vec<Collision>,
+}
+
+impl<'a> CartSim<'a> {
+    fn new(track: &'a Track, carts: &[Cart]) -> CartSim<'a> {
+        let mut occupancy = Array2::from_elem(track.grid.dim(), None);
+        for (id, cart) in carts.iter().enumerate() {
+            occupancy[(cart.y, cart.x)] = Some(id);
+        }
+
+        CartSim {
+            track,
+            carts: carts.to_vec(),
+            order: (0..carts.len()).collect(),
+            occupancy,
+            ticks: 0,
+            collisions: vec![],
+        }
+    }
+
+    // Returns this tick's collisions.
+    fn tick(&mut self) -> &[Collision] {
+        self.ticks += 1;
+        let first_new = self.collisions.len();
+        let carts = &self.carts;
+        self.order.sort_by_key(|&id| (carts[id].y, carts[id].x));
+
+        for &id in &self.order {
+            let cart = &mut self.carts[id];
+            if cart.is_removed {
+                continue;
+            }
+            self.occupancy[(cart.y, cart.x)] = None;
+            cart.move_cart(&self.track.grid);
+            let (y, x) = (cart.y, cart.x);
+
+            match self.occupancy[(y, x)].take() {
+                Some(other) => {
+                    self.carts[id].is_removed = true;
+                    self.carts[other].is_removed = true;
+                    self.collisions.push(Collision {
+                        tick: self.ticks,
+                        y,
+                        x,
+                        carts: [id, other],
+                    });
+                }
+                None => self.occupancy[(y, x)] = Some(id),
+            }
+        }
+
+        let carts = &self.carts;
+        self.order.retain(|&id| !carts[id].is_removed);
+        &self.collisions[first_new..]
+    }
+
+    fn run_until_one_left(&mut self) {
+        while self.order.len() > 1 {
+            self.tick();
+        }
+    }
+
+    fn collisions(&self) -> &[Collision] {
+        &self.collisions
+    }
+
+    fn carts(&self) -> impl Iterator<Item = &Cart> {
+        self.order.iter().map(|&id| &self.carts[id])
+    }
+
+    // Crashes from the last tick are drawn as `X`.
+    fn render(&self) -> String {
+        let mut grid = self.track.grid.clone();
+        for cart in self.carts() {
+            grid[(cart.y, cart.x)] = cart.dir.into();
         }
+        for c in self
+            .collisions
+            .iter()
+            .rev()
+            .take_while(|c| c.tick == self.ticks)
+        {
+            grid[(c.y, c.x)] = b'X';
+        }
+
+        grid.outer_iter()
+            .map(|row| row.iter().map(|&c| c as char).join("") + "\n")
+            .join("")
     }
 }
 
-fn parse(raw_inp: &str) -> (Array2<u8>, Vec<Cart>) {
+fn parse(raw_inp: &str) -> (Track, Vec<Cart>) {
     let mut grid = make_byte_grid(raw_inp);
     let mut carts: Vec<Cart> = vec![];
 
@@ -96,65 +233,63 @@ fn parse(raw_inp: &str) -> (Array2<u8>, Vec<Cart>) {
         _ => {}
     });
 
-    (grid, carts)
+    (Track { grid }, carts)
 }
 
-fn calculate(data: &(Array2<u8>, Vec<Cart>)) -> (String, String) {
-    let mut p1 = None;
-    let grid = &data.0;
-    let mut carts = data.1.iter().map(|c| RefCell::new(*c)).collect::<Vec<_>>();
-    while carts.iter().filter(|c| !c.borrow().is_removed).count() > 1 {
-        carts.sort_unstable();
-
-        for cart in carts.iter() {
-            let (y, x) = {
-                let mut c = cart.borrow_mut();
-                if c.is_removed {
-                    continue;
-                }
-                c.move_cart(grid);
-                (c.y, c.x)
-            };
-
-            if carts
-                .iter()
-                .filter(|other| {
-                    let other = other.borrow();
-                    !other.is_removed && other.y == y && other.x == x
-                })
-                .count()
-                != 1
-            {
-                carts.iter().for_each(|other| {
-                    let mut other = other.borrow_mut();
-                    if other.x == x && other.y == y {
-                        other.is_removed = true;
-                    }
-                });
-
-                if p1.is_none() {
-                    p1 = Some(format!("{},{}", x, y))
-                }
-            }
-        }
-    }
+fn calculate(data: &(Track, Vec<Cart>)) -> (String, String) {
+    let mut sim = CartSim::new(&data.0, &data.1);
+    sim.run_until_one_left();
 
     (
-        p1.unwrap_or("no p1 answer".to_owned()),
-        carts
-            .iter()
-            .find(|c| !c.borrow().is_removed)
-            .map(|c| format!("{},{}", c.borrow().x, c.borrow().y))
+        sim.collisions()
+            .first()
+            .map(|c| format!("{},{}", c.x, c.y))
+            .unwrap_or("no p1 answer".to_owned()),
+        sim.carts()
+            .next()
+            .map(|c| format!("{},{}", c.x, c.y))
             .unwrap_or("no p2 answer".to_owned()),
     )
 }
 
+#[derive(Parser)]
+struct Day13Cli {
+    #[clap(flatten)]
+    cli: Cli,
+
+    /// Print every collision until at most one cart is left instead of the
+    /// answers.
+    #[clap(long)]
+    collisions: bool,
+
+    /// Print the track after this many ticks instead of the answers.
+    #[clap(long)]
+    render_after: Option<usize>,
+}
+
 fn main() {
-    let args = Cli::parse();
+    let args = Day13Cli::parse();
 
-    let inp = fs::read_to_string(args.input).expect("can't open input file");
+    let inp = fs::read_to_string(&args.cli.input).expect("can't open input file");
 
     let data = parse(&inp);
+
+    if args.collisions {
+        let mut sim = CartSim::new(&data.0, &data.1);
+        sim.run_until_one_left();
+        sim.collisions().iter().for_each(|c| println!("{c}"));
+        return;
+    }
+
+    if let Some(ticks) = args.render_after {
+        let mut sim = CartSim::new(&data.0, &data.1);
+        (0..ticks).for_each(|_| {
+            sim.tick();
+        });
+        print!("{}", sim.render());
+        return;
+    }
+
     let (p1, p2) = calculate(&data);
     println!("{p1}\n{p2}");
 }
@@ -164,6 +299,7 @@ mod tests {
     use super::*;
 
     const EXAMPLE_DATA: &str = include_str!("../../inputs/examples/2018_13");
+    const EXAMPLE_DATA_2: &str = include_str!("../../inputs/examples/2018_13_2");
     const REAL_DATA: &str = include_str!("../../inputs/real/2018_13");
 
     #[test]
@@ -171,6 +307,169 @@ mod tests {
         assert_eq!(calculate(&parse(EXAMPLE_DATA)).0, "7,3");
     }
 
+    #[test]
+    fn test_render_first_crash() {
+        let (track, carts) = parse(EXAMPLE_DATA);
+        let mut sim = CartSim::new(&track, &carts);
+        (0..13).for_each(|_| assert!(sim.tick().is_empty()));
+        assert_eq!(
+            sim.tick(),
+            [Collision {
+                tick: 14,
+                y: 3,
+                x: 7,
+                carts: [0, 1]
+            }]
+        );
+        assert_eq!(
+            sim.render(),
+            "\
+/---\\        
+|   |  /----\\
+| /-+--+-\\  |
+| | |  X |  |
+\\-+-/  \\-+--/
+  \\------/   
+"
+        );
+    }
+
+    #[test]
+    fn test_collision_log() {
+        let (track, carts) = parse(EXAMPLE_DATA_2);
+        let mut sim = CartSim::new(&track, &carts);
+        sim.run_until_one_left();
+        assert_eq!(
+            sim.collisions()
+                .iter()
+                .map(|c| (c.tick, c.x, c.y))
+                .collect::<Vec<_>>(),
+            [(1, 2, 0), (1, 2, 4), (1, 6, 4), (3, 2, 4)]
+        );
+        assert_eq!(
+            sim.render(),
+            "\
+/---\\  
+|   |  
+| /-+-\\
+| | | |
+\\-X-/ ^
+  |   |
+  \\---/
+"
+        );
+    }
+
+    #[test]
+    fn test_p2_example() {
+        assert_eq!(calculate(&parse(EXAMPLE_DATA_2)).1, "6,4");
+    }
+
+    // Loops use their own rows and columns, so they only meet at `+`.
+    fn random_track(seed: u64, loops: usize, carts: usize) -> String {
+        let mut rng = fastrand::Rng::with_seed(seed);
+        let size = 4 * loops + 2;
+        let mut lines = || {
+            let mut lines = (0..size).collect::<Vec<_>>();
+            rng.shuffle(&mut lines);
+            lines
+        };
+        let (ys, xs) = (lines(), lines());
+
+        let mut grid = Array2::from_elem((size, size), b' ');
+        let rects = (0..loops)
+            .map(|i| {
+                let (y0, y1) = (ys[2 * i].min(ys[2 * i + 1]), ys[2 * i].max(ys[2 * i + 1]));
+                let (x0, x1) = (xs[2 * i].min(xs[2 * i + 1]), xs[2 * i].max(xs[2 * i + 1]));
+                (y0, y1, x0, x1)
+            })
+            .collect::<Vec<_>>();
+        for &(y0, y1, x0, x1) in &rects {
+            for x in x0 + 1..x1 {
+                for y in [y0, y1] {
+                    grid[(y, x)] = if grid[(y, x)] == b'|' { b'+' } else { b'-' };
+                }
+            }
+            for y in y0 + 1..y1 {
+                for x in [x0, x1] {
+                    grid[(y, x)] = if grid[(y, x)] == b'-' { b'+' } else { b'|' };
+                }
+            }
+            grid[(y0, x0)] = b'/';
+            grid[(y1, x1)] = b'/';
+            grid[(y0, x1)] = b'\\';
+            grid[(y1, x0)] = b'\\';
+        }
+
+        for _ in 0..carts {
+            let (y, x) = (rng.usize(..size), rng.usize(..size));
+            grid[(y, x)] = match (grid[(y, x)], rng.bool()) {
+                (b'-', true) => b'<',
+                (b'-', false) => b'>',
+                (b'|', true) => b'^',
+                (b'|', false) => b'v',
+                (c, _) => c,
+            };
+        }
+
+        grid.outer_iter()
+            .map(|row| row.iter().map(|&c| c as char).join("") + "\n")
+            .join("")
+    }
+
+    // The original simulation, scanning every cart after each move.
+    fn reference_collisions(track: &Track, carts: &[Cart], ticks: usize) -> Vec<Collision> {
+        let mut carts = carts.iter().copied().enumerate().collect::<Vec<_>>();
+        let mut collisions = vec![];
+        for tick in 1..=ticks {
+            if carts.iter().filter(|(_, c)| !c.is_removed).count() <= 1 {
+                break;
+            }
+            carts.sort_by_key(|(_, c)| (c.y, c.x));
+            for i in 0..carts.len() {
+                if carts[i].1.is_removed {
+                    continue;
+                }
+                carts[i].1.move_cart(&track.grid);
+                let (id, Cart { y, x, .. }) = carts[i];
+                if let Some(j) = (0..carts.len()).find(|&j| {
+                    j != i && !carts[j].1.is_removed && (carts[j].1.y, carts[j].1.x) == (y, x)
+                }) {
+                    carts[i].1.is_removed = true;
+                    carts[j].1.is_removed = true;
+                    collisions.push(Collision {
+                        tick,
+                        y,
+                        x,
+                        carts: [id, carts[j].0],
+                    });
+                }
+            }
+        }
+        collisions
+    }
+
+    #[test]
+    fn test_matches_reference() {
+        let mut total = 0;
+        for seed in 0..100 {
+            let raw = random_track(seed, 2 + seed as usize % 8, 2 + seed as usize % 30);
+            let (track, carts) = parse(&raw);
+            let mut sim = CartSim::new(&track, &carts);
+            while sim.carts().count() > 1 && sim.ticks < 1000 {
+                sim.tick();
+            }
+            assert_eq!(
+                sim.collisions(),
+                reference_collisions(&track, &carts, 1000),
+                "seed {seed}:\n{raw}"
+            );
+            total += sim.collisions().len();
+        }
+        // Guard against the generator producing tracks where nothing happens.
+        assert!(total > 100);
+    }
+
     #[test]
     fn test_p1_real() {
         assert_eq!(calculate(&parse(REAL_DATA)).0, "74,87");
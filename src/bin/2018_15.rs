@@ -1,217 +1,402 @@
 use advent_of_code_2018::{Cli, Parser, grid_util::make_byte_grid};
-use ahash::AHashSet;
 use itertools::Itertools;
 use ndarray::Array2;
-use std::cell::{RefCell, RefMut};
+use rayon::prelude::*;
 use std::collections::VecDeque;
+use std::fmt;
 use std::fs;
+use std::str::FromStr;
 
-#[derive(PartialEq, Eq, Debug, Clone, PartialOrd, Ord)]
-enum UnitClass {
-    Goblin,
-    Elf,
+type TeamId = usize;
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+struct TeamRules {
+    symbol: u8,
+    hp: i64,
+    attack_power: i64,
 }
 
-impl From<u8> for UnitClass {
-    fn from(value: u8) -> Self {
-        match value {
-            b'G' => UnitClass::Goblin,
-            b'E' => UnitClass::Elf,
-            _ => panic!("Unhandled unit class"),
+#[derive(PartialEq, Eq, Debug, Clone)]
+struct Rules {
+    teams: Vec<TeamRules>,
+    diagonal_movement: bool,
+}
+
+const GOBLIN: u8 = b'G';
+const ELF: u8 = b'E';
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            teams: vec![
+                TeamRules {
+                    symbol: GOBLIN,
+                    hp: 200,
+                    attack_power: 3,
+                },
+                TeamRules {
+                    symbol: ELF,
+                    hp: 200,
+                    attack_power: 3,
+                },
+            ],
+            diagonal_movement: false,
         }
     }
 }
 
+impl Rules {
+    fn team_of(&self, symbol: u8) -> Option<TeamId> {
+        self.teams.iter().position(|t| t.symbol == symbol)
+    }
+
+    fn team_mut(&mut self, symbol: u8) -> &mut TeamRules {
+        self.teams
+            .iter_mut()
+            .find(|t| t.symbol == symbol)
+            .expect("no such team")
+    }
+
+    // In reading order.
+    fn neighbours_of(&self, y: usize, x: usize) -> impl Iterator<Item = (usize, usize)> + use<> {
+        let diagonal = self.diagonal_movement;
+        [
+            (-1, -1),
+            (-1, 0),
+            (-1, 1),
+            (0, -1),
+            (0, 1),
+            (1, -1),
+            (1, 0),
+            (1, 1),
+        ]
+        .into_iter()
+        .filter(move |(dy, dx): &(isize, isize)| diagonal || dy.abs() + dx.abs() == 1)
+        .map(move |(dy, dx)| (y.wrapping_add_signed(dy), x.wrapping_add_signed(dx)))
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Clone, PartialOrd, Ord)]
 struct Unit {
     y: usize,
     x: usize,
-    class: UnitClass,
+    team: TeamId,
     hp: i64,
 }
 
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum Event {
+    Moved {
+        unit: usize,
+        y: usize,
+        x: usize,
+    },
+    Attacked {
+        attacker: usize,
+        target: usize,
+        damage: i64,
+    },
+    Died {
+        unit: usize,
+    },
+    RoundEnded {
+        round: i64,
+    },
+    CombatEnded {
+        rounds: i64,
+        outcome: i64,
+    },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Moved { unit, y, x } => write!(f, "move {unit} {y} {x}"),
+            Event::Attacked {
+                attacker,
+                target,
+                damage,
+            } => write!(f, "attack {attacker} {target} {damage}"),
+            Event::Died { unit } => write!(f, "die {unit}"),
+            Event::RoundEnded { round } => write!(f, "round {round}"),
+            Event::CombatEnded { rounds, outcome } => write!(f, "end {rounds} {outcome}"),
+        }
+    }
+}
+
+impl FromStr for Event {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let kind = words.next().ok_or(())?;
+        let args = words
+            .map(|w| w.parse::<i64>().map_err(|_| ()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let idx = |i: usize| args.get(i).copied().ok_or(());
+        let uidx = |i: usize| idx(i).and_then(|v| usize::try_from(v).map_err(|_| ()));
+
+        Ok(match kind {
+            "move" => Event::Moved {
+                unit: uidx(0)?,
+                y: uidx(1)?,
+                x: uidx(2)?,
+            },
+            "attack" => Event::Attacked {
+                attacker: uidx(0)?,
+                target: uidx(1)?,
+                damage: idx(2)?,
+            },
+            "die" => Event::Died { unit: uidx(0)? },
+            "round" => Event::RoundEnded { round: idx(0)? },
+            "end" => Event::CombatEnded {
+                rounds: idx(0)?,
+                outcome: idx(1)?,
+            },
+            _ => return Err(()),
+        })
+    }
+}
+
 impl Unit {
     fn is_alive(&self) -> bool {
         self.hp > 0
     }
+}
 
-    fn is_adjacent(&self, other: &Unit) -> bool {
-        self.x.abs_diff(other.x) + self.y.abs_diff(other.y) == 1
+// Squares are only valid if marked with the current stamp, so the buffers
+// never need clearing between searches.
+#[derive(Clone, Default)]
+struct PathScratch {
+    stamp: u32,
+    seen: Array2<u32>,
+    target: Array2<u32>,
+    dist: Array2<u32>,
+    first_step: Array2<(usize, usize)>,
+    queue: VecDeque<(usize, usize)>,
+}
+
+impl PathScratch {
+    fn begin(&mut self, dim: (usize, usize)) {
+        if self.seen.dim() != dim || self.stamp == u32::MAX {
+            *self = PathScratch {
+                stamp: 0,
+                seen: Array2::zeros(dim),
+                target: Array2::zeros(dim),
+                dist: Array2::zeros(dim),
+                first_step: Array2::from_elem(dim, (0, 0)),
+                queue: VecDeque::default(),
+            };
+        }
+        self.stamp += 1;
+        self.queue.clear();
     }
 
-    fn valid_movement_targets(&self, state: &GameState) -> AHashSet<(usize, usize)> {
-        state
-            .units
-            .iter()
-            .filter_map(|u| u.try_borrow().ok())
-            .filter(|unit| unit.is_alive() && unit.class != self.class)
-            .flat_map(|unit| {
-                [
-                    (unit.y - 1, unit.x),
-                    (unit.y, unit.x - 1),
-                    (unit.y, unit.x + 1),
-                    (unit.y + 1, unit.x),
-                ]
-            })
-            .filter(|&(y, x)| state.is_empty_square(y, x))
-            .collect()
+    fn visit(&mut self, pos: (usize, usize), dist: u32, first_step: (usize, usize)) {
+        self.seen[pos] = self.stamp;
+        self.dist[pos] = dist;
+        self.first_step[pos] = first_step;
+        self.queue.push_back(pos);
     }
+}
+
+// A unit's position in reading order at the start of combat.
+type UnitId = usize;
+
+#[derive(Clone)]
+struct GameState {
+    board: Array2<u8>,
+    units: Vec<Unit>,
+    occupancy: Array2<Option<UnitId>>,
+    round: i64,
+    rules: Rules,
+    events: Option<Vec<Event>>,
+    casualties: Vec<usize>,
+    abort_on_death_of: Option<TeamId>,
+    scratch: PathScratch,
+}
 
-    fn pathfind(
-        &self,
-        sy: usize,
-        sx: usize,
-        targets: &AHashSet<(usize, usize)>,
-        state: &GameState,
-    ) -> Vec<(i64, usize, usize)> {
-        debug_assert!(self.y.abs_diff(sy) + self.x.abs_diff(sx) == 1);
+impl GameState {
+    fn record_events(&mut self) {
+        self.events = Some(vec![]);
+    }
 
-        if targets.is_empty() {
-            return vec![];
+    fn emit(&mut self, event: Event) {
+        if let Some(events) = &mut self.events {
+            events.push(event);
         }
+    }
 
-        let mut costs = Array2::from_shape_simple_fn(state.board.dim(), || i64::MAX);
-        let mut q = VecDeque::default();
+    fn take_events(&mut self) -> Vec<Event> {
+        self.events.take().unwrap_or_default()
+    }
 
-        costs[(sy, sx)] = 1;
-        q.push_back((1, sy, sx));
+    fn is_empty_square(&self, y: usize, x: usize) -> bool {
+        self.board.get((y, x)) == Some(&b'.') && self.occupancy[(y, x)].is_none()
+    }
 
-        while let Some((cost, y, x)) = q.pop_front() {
-            if costs[(y, x)] < cost || targets.contains(&(y, x)) {
-                continue;
-            }
-            state.valid_neighbours_of(y, x).for_each(|(ny, nx)| {
-                if costs[(ny, nx)] > cost + 1 {
-                    costs[(ny, nx)] = cost + 1;
-                    q.push_back((cost + 1, ny, nx));
-                }
-            });
-        }
+    fn valid_neighbours_of(&self, y: usize, x: usize) -> impl Iterator<Item = (usize, usize)> {
+        self.rules
+            .neighbours_of(y, x)
+            .filter(|&(y, x)| self.is_empty_square(y, x))
+    }
 
-        targets
+    fn mark_movement_targets(&self, id: UnitId, scratch: &mut PathScratch) -> bool {
+        let team = self.units[id].team;
+        let mut any = false;
+        self.units
             .iter()
-            .filter_map(|&(ty, tx)| {
-                let cost = costs[(ty, tx)];
-                (cost != i64::MAX).then_some((cost, ty, tx))
-            })
-            .collect()
+            .filter(|unit| unit.is_alive() && unit.team != team)
+            .flat_map(|unit| self.rules.neighbours_of(unit.y, unit.x))
+            .filter(|&(y, x)| self.is_empty_square(y, x))
+            .for_each(|pos| {
+                scratch.target[pos] = scratch.stamp;
+                any = true;
+            });
+        any
     }
 
-    fn movement(&mut self, state: &GameState) {
-        let valid_targets = self.valid_movement_targets(state);
+    // Expands a whole layer at a time, so each square's first step is final
+    // before it is expanded.
+    fn next_step(&self, id: UnitId, scratch: &mut PathScratch) -> Option<(usize, usize)> {
+        scratch.begin(self.board.dim());
+        if !self.mark_movement_targets(id, scratch) {
+            return None;
+        }
 
-        let target = state
-            .valid_neighbours_of(self.y, self.x)
-            .flat_map(|(sy, sx)| {
-                self.pathfind(sy, sx, &valid_targets, state)
-                    .into_iter()
-                    .map(move |(c, ty, tx)| (c, ty, tx, sy, sx))
-            })
-            .sorted_unstable()
-            .next()
-            .map(|(_, _, _, y, x)| (y, x));
-
-        if let Some((y, x)) = target {
-            let mut occupied = state.occupied_squares.borrow_mut();
-            occupied.remove(&(self.y, self.x));
-            occupied.insert((y, x));
-            self.y = y;
-            self.x = x;
+        let Unit { y, x, .. } = self.units[id];
+        for pos in self.valid_neighbours_of(y, x) {
+            scratch.visit(pos, 1, pos);
         }
-    }
 
-    fn take_turn(&mut self, state: &GameState) {
-        if let Some(mut opponent) = self.opponent_in_range(state) {
-            self.attack(&mut opponent, state);
-        } else {
-            self.movement(state);
+        let mut best = None;
+        while best.is_none() && !scratch.queue.is_empty() {
+            for _ in 0..scratch.queue.len() {
+                let pos = scratch.queue.pop_front().expect("nonempty");
+                if scratch.target[pos] == scratch.stamp {
+                    best = Some(best.map_or(pos, |b: (usize, usize)| b.min(pos)));
+                }
+                if best.is_some() {
+                    continue;
+                }
 
-            if let Some(mut opponent) = self.opponent_in_range(state) {
-                self.attack(&mut opponent, state);
+                let (dist, first_step) = (scratch.dist[pos], scratch.first_step[pos]);
+                for n in self.valid_neighbours_of(pos.0, pos.1) {
+                    if scratch.seen[n] != scratch.stamp {
+                        scratch.visit(n, dist + 1, first_step);
+                    } else if scratch.dist[n] == dist + 1 && first_step < scratch.first_step[n] {
+                        scratch.first_step[n] = first_step;
+                    }
+                }
             }
         }
+
+        best.map(|target| scratch.first_step[target])
     }
 
-    fn attack(&self, opp: &mut Unit, state: &GameState) {
-        opp.hp -= if opp.class == UnitClass::Goblin {
-            state.elf_attack_power
-        } else {
-            3
-        };
+    fn movement(&mut self, id: UnitId) {
+        let mut scratch = std::mem::take(&mut self.scratch);
+        let target = self.next_step(id, &mut scratch);
+        self.scratch = scratch;
+        let Unit { y, x, .. } = self.units[id];
 
-        if !opp.is_alive() {
-            state.occupied_squares.borrow_mut().remove(&(opp.y, opp.x));
+        if let Some((ny, nx)) = target {
+            self.occupancy[(y, x)] = None;
+            self.occupancy[(ny, nx)] = Some(id);
+            self.units[id].y = ny;
+            self.units[id].x = nx;
+            self.emit(Event::Moved {
+                unit: id,
+                y: ny,
+                x: nx,
+            });
         }
     }
 
-    fn opponent_in_range<'a>(&self, state: &'a GameState) -> Option<RefMut<'a, Unit>> {
-        state
-            .units
-            .iter()
-            .filter_map(|u| u.try_borrow_mut().ok())
-            .filter(|unit| unit.class != self.class && unit.is_alive() && self.is_adjacent(unit))
-            .sorted_unstable_by_key(|unit| (unit.hp, unit.y, unit.x))
-            .next()
+    fn take_turn(&mut self, id: UnitId) {
+        if let Some(opponent) = self.opponent_in_range(id) {
+            self.attack(id, opponent);
+        } else {
+            self.movement(id);
+
+            if let Some(opponent) = self.opponent_in_range(id) {
+                self.attack(id, opponent);
+            }
+        }
     }
-}
 
-#[derive(Clone)]
-struct GameState {
-    board: Array2<u8>,
-    units: Vec<RefCell<Unit>>,
-    round: i64,
-    elf_attack_power: i64,
-    occupied_squares: RefCell<AHashSet<(usize, usize)>>,
-}
+    fn attack(&mut self, attacker: UnitId, target: UnitId) {
+        let damage = self.rules.teams[self.units[attacker].team].attack_power;
+        let opp = &mut self.units[target];
+        opp.hp -= damage;
+        let (died, y, x, team) = (!opp.is_alive(), opp.y, opp.x, opp.team);
 
-impl GameState {
-    fn is_empty_square(&self, y: usize, x: usize) -> bool {
-        self.board.get((y, x)) == Some(&b'.') && !self.occupied_squares.borrow().contains(&(y, x))
-    }
+        self.emit(Event::Attacked {
+            attacker,
+            target,
+            damage,
+        });
 
-    fn valid_neighbours_of(&self, y: usize, x: usize) -> impl Iterator<Item = (usize, usize)> {
-        [(y - 1, x), (y, x - 1), (y, x + 1), (y + 1, x)]
-            .into_iter()
-            .filter(|&(y, x)| self.is_empty_square(y, x))
+        if died {
+            self.occupancy[(y, x)] = None;
+            self.casualties[team] += 1;
+            self.emit(Event::Died { unit: target });
+        }
     }
 
-    fn left(&self) -> (usize, usize) {
-        self.units
-            .iter()
-            .map(|u| u.borrow())
-            .filter(|u| u.is_alive())
-            .fold((0, 0), |(g, e), u| match u.class {
-                UnitClass::Goblin => (g + 1, e),
-                UnitClass::Elf => (g, e + 1),
+    fn opponent_in_range(&self, id: UnitId) -> Option<UnitId> {
+        let unit = &self.units[id];
+        self.rules
+            .neighbours_of(unit.y, unit.x)
+            .filter_map(|pos| self.occupancy.get(pos).copied().flatten())
+            .filter(|&other| self.units[other].team != unit.team)
+            .min_by_key(|&other| {
+                let other = &self.units[other];
+                (other.hp, other.y, other.x)
             })
     }
 
+    fn has_enemies(&self, id: UnitId) -> bool {
+        let team = self.units[id].team;
+        self.units.iter().any(|u| u.is_alive() && u.team != team)
+    }
+
     fn take_turns(&mut self) -> bool {
-        for unit in self.units.iter() {
-            if !unit.borrow().is_alive() {
+        let turn_order = (0..self.units.len())
+            .filter(|&id| self.units[id].is_alive())
+            .sorted_unstable_by_key(|&id| (self.units[id].y, self.units[id].x))
+            .collect::<Vec<_>>();
+
+        for id in turn_order {
+            if !self.units[id].is_alive() {
                 continue;
             }
 
-            let (g, e) = self.left();
-            if g == 0 || e == 0 {
+            let aborted = self
+                .abort_on_death_of
+                .is_some_and(|team| self.casualties[team] > 0);
+            if aborted || !self.has_enemies(id) {
                 return false;
             }
 
-            unit.borrow_mut().take_turn(self);
+            self.take_turn(id);
         }
         true
     }
 
     fn play_single_round(&mut self) -> Option<i64> {
-        self.units.sort_unstable();
-
         let was_full_round = self.take_turns();
 
         if !was_full_round {
-            return Some(self.outcome());
+            let outcome = self.outcome();
+            self.emit(Event::CombatEnded {
+                rounds: self.round,
+                outcome,
+            });
+            return Some(outcome);
         }
 
         self.round += 1;
+        self.emit(Event::RoundEnded { round: self.round });
         None
     }
 
@@ -228,41 +413,112 @@ impl GameState {
             * self
                 .units
                 .iter()
-                .filter(|&unit| unit.borrow().is_alive())
-                .map(|unit| unit.borrow().hp)
+                .filter(|unit| unit.is_alive())
+                .map(|unit| unit.hp)
                 .sum::<i64>()
     }
 }
 
 fn parse(raw_inp: &str) -> GameState {
+    parse_with_rules(raw_inp, Rules::default())
+}
+
+fn parse_with_rules(raw_inp: &str, rules: Rules) -> GameState {
     let mut board = make_byte_grid(raw_inp);
 
     let mut units = vec![];
-    let mut occupied_squares = AHashSet::default();
+    let mut occupancy = Array2::from_elem(board.dim(), None);
 
-    board
-        .indexed_iter_mut()
-        .filter(|(_, elem)| *elem == &b'G' || *elem == &b'E')
-        .for_each(|((y, x), elem)| {
-            units.push(RefCell::new(Unit {
+    board.indexed_iter_mut().for_each(|((y, x), elem)| {
+        if let Some(team) = rules.team_of(*elem) {
+            occupancy[(y, x)] = Some(units.len());
+            units.push(Unit {
                 y,
                 x,
-                class: (*elem).into(),
-                hp: 200,
-            }));
-
-            occupied_squares.insert((y, x));
+                team,
+                hp: rules.teams[team].hp,
+            });
 
             *elem = b'.';
-        });
+        }
+    });
 
     GameState {
         board,
         units,
+        occupancy,
         round: 0,
-        elf_attack_power: 3,
-        occupied_squares: RefCell::new(occupied_squares),
+        casualties: vec![0; rules.teams.len()],
+        rules,
+        events: None,
+        abort_on_death_of: None,
+        scratch: PathScratch::default(),
+    }
+}
+
+fn render(board: &Array2<u8>, units: &[Unit], rules: &Rules) -> String {
+    let mut out = String::new();
+    for (y, row) in board.outer_iter().enumerate() {
+        let mut row_units = units
+            .iter()
+            .filter(|u| u.is_alive() && u.y == y)
+            .collect::<Vec<_>>();
+        row_units.sort_unstable_by_key(|u| u.x);
+
+        out.extend(row.iter().enumerate().map(|(x, &c)| {
+            row_units
+                .iter()
+                .find(|u| u.x == x)
+                .map_or(c, |u| rules.teams[u.team].symbol) as char
+        }));
+        if !row_units.is_empty() {
+            out.push_str("   ");
+            out.push_str(
+                &row_units
+                    .iter()
+                    .map(|u| format!("{}({})", rules.teams[u.team].symbol as char, u.hp))
+                    .join(", "),
+            );
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn replay(initial: &GameState, events: &[Event]) -> String {
+    let mut units = initial.units.clone();
+
+    let mut frames = vec![(0, render(&initial.board, &units, &initial.rules))];
+
+    for event in events {
+        match *event {
+            Event::Moved { unit, y, x } => {
+                units[unit].y = y;
+                units[unit].x = x;
+            }
+            Event::Attacked { target, damage, .. } => units[target].hp -= damage,
+            Event::Died { unit } => debug_assert!(!units[unit].is_alive()),
+            Event::RoundEnded { round } => {
+                frames.push((round, render(&initial.board, &units, &initial.rules)))
+            }
+            Event::CombatEnded { rounds, .. } => {
+                // The puzzle labels the final state with the last full round.
+                if frames.last().is_some_and(|&(r, _)| r == rounds) {
+                    frames.pop();
+                }
+                frames.push((rounds, render(&initial.board, &units, &initial.rules)));
+            }
+        }
     }
+
+    frames
+        .into_iter()
+        .map(|(round, frame)| match round {
+            0 => format!("Initially:\n{frame}"),
+            1 => format!("After 1 round:\n{frame}"),
+            r => format!("After {r} rounds:\n{frame}"),
+        })
+        .join("\n")
 }
 
 fn calculate_p1(data: &GameState) -> i64 {
@@ -270,30 +526,143 @@ fn calculate_p1(data: &GameState) -> i64 {
     state.play()
 }
 
-fn calculate_p2(data: &GameState) -> i64 {
-    let (_, initial_elves) = data.left();
+fn play_without_elf_deaths(mut state: GameState, elf_attack_power: i64) -> Option<i64> {
+    // Without an elf team there is nobody to protect.
+    let elves = state.rules.team_of(ELF);
+    if let Some(team) = elves {
+        state.rules.teams[team].attack_power = elf_attack_power;
+    }
+    state.abort_on_death_of = elves;
+    let outcome = state.play();
+    elves
+        .is_none_or(|team| state.casualties[team] == 0)
+        .then_some(outcome)
+}
+
+#[derive(PartialEq, Eq, Debug)]
+struct NoWinningPower;
 
-    for elf_attack in 4..200 {
-        let mut state = data.clone();
-        state.elf_attack_power = elf_attack;
+impl fmt::Display for NoWinningPower {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no elf attack power up to 200 wins without losses")
+    }
+}
 
-        let outcome = state.play();
-        let (_, elves) = state.left();
-        if elves == initial_elves {
-            return outcome;
+fn calculate_p2(data: &GameState) -> Result<i64, NoWinningPower> {
+    // Assumes that winning without losses at some power means winning at every
+    // higher power too. Powers in lo+1..hi are left to probe.
+    let mut lo = 3;
+    let mut hi = 201;
+    let mut best = None;
+    let probes = rayon::current_num_threads().max(2) as i64;
+
+    while hi - lo > 1 {
+        let span = hi - lo - 1;
+        let candidates = (0..probes.min(span))
+            .map(|i| lo + 1 + (span - 1) * i / (probes.min(span) - 1).max(1))
+            .dedup()
+            .collect::<Vec<_>>();
+
+        let results = candidates
+            .into_par_iter()
+            .map(|power| (power, play_without_elf_deaths(data.clone(), power)))
+            .collect::<Vec<_>>();
+
+        match results.iter().position(|(_, outcome)| outcome.is_some()) {
+            Some(i) => {
+                hi = results[i].0;
+                best = results[i].1;
+                if i > 0 {
+                    lo = results[i - 1].0;
+                }
+            }
+            None => lo = results.last().expect("nonempty").0,
         }
     }
-    panic!("no p2 answer");
+
+    best.ok_or(NoWinningPower)
+}
+
+#[derive(Parser)]
+struct Day15Cli {
+    #[clap(flatten)]
+    cli: Cli,
+
+    /// Print the event log of a battle instead of the answers.
+    #[clap(long)]
+    log: bool,
+
+    /// Print the board after every round of a battle instead of the answers.
+    #[clap(long)]
+    replay: bool,
+
+    /// Print the board after every round of a previously saved event log.
+    #[clap(long)]
+    replay_from: Option<String>,
+
+    /// Elf attack power for the logged or replayed battle.
+    #[clap(long, default_value_t = 3)]
+    elf_attack_power: i64,
+
+    /// Goblin attack power for the logged or replayed battle.
+    #[clap(long, default_value_t = 3)]
+    goblin_attack_power: i64,
+
+    /// Starting hit points of every unit in the logged or replayed battle.
+    #[clap(long, default_value_t = 200)]
+    hit_points: i64,
+
+    /// Allow diagonal movement and attacks in the logged or replayed battle.
+    #[clap(long)]
+    diagonal_movement: bool,
+}
+
+impl Day15Cli {
+    fn rules(&self) -> Rules {
+        let mut rules = Rules {
+            diagonal_movement: self.diagonal_movement,
+            ..Rules::default()
+        };
+        rules.team_mut(ELF).attack_power = self.elf_attack_power;
+        rules.team_mut(GOBLIN).attack_power = self.goblin_attack_power;
+        rules.teams.iter_mut().for_each(|t| t.hp = self.hit_points);
+        rules
+    }
 }
 
 fn main() {
-    let args = Cli::parse();
+    let args = Day15Cli::parse();
 
-    let inp = fs::read_to_string(args.input).expect("can't open input file");
+    let inp = fs::read_to_string(&args.cli.input).expect("can't open input file");
+
+    if let Some(log) = &args.replay_from {
+        let events = fs::read_to_string(log)
+            .expect("can't open log file")
+            .lines()
+            .map(|line| line.parse().expect("invalid event"))
+            .collect::<Vec<_>>();
+        print!("{}", replay(&parse_with_rules(&inp, args.rules()), &events));
+        return;
+    }
+
+    if args.log || args.replay {
+        let initial = parse_with_rules(&inp, args.rules());
+        let mut state = initial.clone();
+        state.record_events();
+        state.play();
+        let events = state.take_events();
+
+        if args.log {
+            events.iter().for_each(|e| println!("{e}"));
+        } else {
+            print!("{}", replay(&initial, &events));
+        }
+        return;
+    }
 
     let data = parse(&inp);
     let p1 = calculate_p1(&data);
-    let p2 = calculate_p2(&data);
+    let p2 = calculate_p2(&data).unwrap_or_else(|e| panic!("{e}"));
     println!("{p1}\n{p2}");
 }
 
@@ -340,6 +709,193 @@ mod tests {
         assert_eq!(calculate_p1(&parse(EXAMPLE_DATA_6)), 18740);
     }
 
+    fn logged_battle(data: &GameState) -> Vec<Event> {
+        let mut state = data.clone();
+        state.record_events();
+        state.play();
+        state.take_events()
+    }
+
+    #[test]
+    fn test_replay_example() {
+        let data = parse(EXAMPLE_DATA);
+        let replayed = replay(&data, &logged_battle(&data));
+
+        assert!(replayed.contains(
+            "After 1 round:
+#######
+#..G..#   G(200)
+#...EG#   E(197), G(197)
+#.#G#G#   G(200), G(197)
+#...#E#   E(197)
+#.....#
+#######
+"
+        ));
+        assert!(replayed.ends_with(
+            "After 47 rounds:
+#######
+#G....#   G(200)
+#.G...#   G(131)
+#.#.#G#   G(59)
+#...#.#
+#....G#   G(200)
+#######
+"
+        ));
+    }
+
+    #[test]
+    fn test_event_log_round_trip() {
+        let events = logged_battle(&parse(EXAMPLE_DATA_2));
+        let parsed = events
+            .iter()
+            .map(|e| e.to_string().parse())
+            .collect::<Result<Vec<Event>, _>>();
+        assert_eq!(parsed, Ok(events));
+    }
+
+    #[test]
+    fn test_three_teams() {
+        let mut rules = Rules::default();
+        rules.teams.push(TeamRules {
+            symbol: b'D',
+            hp: 200,
+            attack_power: 10,
+        });
+        let mut state = parse_with_rules("#####\n#GED#\n#####", rules);
+
+        // The elf dies in round 16, then the goblin in round 32.
+        assert_eq!(state.play(), 32 * 152);
+        assert_eq!(state.round, 32);
+        let survivors = state
+            .units
+            .iter()
+            .filter(|u| u.is_alive())
+            .map(|u| (u.team, u.hp))
+            .collect::<Vec<_>>();
+        assert_eq!(survivors, [(2, 152)]);
+        assert_eq!(state.casualties, [1, 1, 0]);
+    }
+
+    #[test]
+    fn test_p2_bounds() {
+        // The goblin kills the elf with one hit, so only a power of 200 kills
+        // it first.
+        let mut rules = Rules::default();
+        rules.teams[0].attack_power = 200;
+        let state = parse_with_rules("####\n#EG#\n####", rules.clone());
+        assert_eq!(calculate_p2(&state), Ok(200));
+
+        rules.teams[0].hp = 201;
+        let state = parse_with_rules("####\n#EG#\n####", rules);
+        assert_eq!(calculate_p2(&state), Err(NoWinningPower));
+    }
+
+    #[test]
+    fn test_p2_matches_linear_scan() {
+        for data in [EXAMPLE_DATA, EXAMPLE_DATA_3, EXAMPLE_DATA_4, EXAMPLE_DATA_5] {
+            let state = parse(data);
+            let linear = (4..=200)
+                .find_map(|power| play_without_elf_deaths(state.clone(), power))
+                .ok_or(NoWinningPower);
+            assert_eq!(calculate_p2(&state), linear);
+        }
+    }
+
+    #[test]
+    fn test_no_elf_team() {
+        let rules = Rules {
+            teams: Rules::default()
+                .teams
+                .into_iter()
+                .filter(|t| t.symbol != ELF)
+                .collect(),
+            ..Rules::default()
+        };
+        let state = parse_with_rules(EXAMPLE_DATA_6.replace('E', ".").as_str(), rules);
+        assert_eq!(
+            play_without_elf_deaths(state.clone(), 10),
+            Some(calculate_p1(&state))
+        );
+    }
+
+    // The original search, from each square next to the unit in turn.
+    fn reference_next_step(state: &GameState, id: UnitId) -> Option<(usize, usize)> {
+        let unit = &state.units[id];
+        let targets = state
+            .units
+            .iter()
+            .filter(|u| u.is_alive() && u.team != unit.team)
+            .flat_map(|u| state.rules.neighbours_of(u.y, u.x))
+            .filter(|&(y, x)| state.is_empty_square(y, x))
+            .collect::<Vec<_>>();
+
+        state
+            .valid_neighbours_of(unit.y, unit.x)
+            .flat_map(|start| {
+                let mut dist = Array2::from_elem(state.board.dim(), usize::MAX);
+                let mut queue = VecDeque::from([start]);
+                dist[start] = 1;
+                while let Some(pos) = queue.pop_front() {
+                    for n in state.valid_neighbours_of(pos.0, pos.1) {
+                        if dist[n] == usize::MAX {
+                            dist[n] = dist[pos] + 1;
+                            queue.push_back(n);
+                        }
+                    }
+                }
+                targets
+                    .iter()
+                    .filter(|&&t| dist[t] != usize::MAX)
+                    .map(|&t| (dist[t], t, start))
+                    .collect::<Vec<_>>()
+            })
+            .min()
+            .map(|(_, _, start)| start)
+    }
+
+    #[test]
+    fn test_next_step_matches_reference() {
+        let mut rng = fastrand::Rng::with_seed(15);
+        for board in 0..100 {
+            let (height, width) = (rng.usize(5..17), rng.usize(5..17));
+            let raw = (0..height)
+                .map(|y| {
+                    (0..width)
+                        .map(|x| match rng.u8(..20) {
+                            _ if y == 0 || x == 0 || y == height - 1 || x == width - 1 => '#',
+                            0..=4 => '#',
+                            5 => 'G',
+                            6 => 'E',
+                            _ => '.',
+                        })
+                        .collect::<String>()
+                })
+                .join("\n");
+            let rules = Rules {
+                diagonal_movement: board % 4 == 0,
+                ..Rules::default()
+            };
+
+            let mut state = parse_with_rules(&raw, rules);
+            for _ in 0..30 {
+                for id in (0..state.units.len()).filter(|&id| state.units[id].is_alive()) {
+                    let mut scratch = PathScratch::default();
+                    assert_eq!(
+                        state.next_step(id, &mut scratch),
+                        reference_next_step(&state, id),
+                        "board {board}, round {}, unit {id}:\n{raw}",
+                        state.round
+                    );
+                }
+                if state.play_single_round().is_some() {
+                    break;
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_p1_real() {
         assert_eq!(calculate_p1(&parse(REAL_DATA)), 257954);
@@ -347,36 +903,36 @@ mod tests {
 
     #[test]
     fn test_p2_example() {
-        assert_eq!(calculate_p2(&parse(EXAMPLE_DATA)), 4988);
+        assert_eq!(calculate_p2(&parse(EXAMPLE_DATA)), Ok(4988));
     }
 
     #[test]
     fn test_p2_example_3() {
-        assert_eq!(calculate_p2(&parse(EXAMPLE_DATA_3)), 31284);
+        assert_eq!(calculate_p2(&parse(EXAMPLE_DATA_3)), Ok(31284));
     }
 
     #[test]
     fn test_p2_example_4() {
-        assert_eq!(calculate_p2(&parse(EXAMPLE_DATA_4)), 3478);
+        assert_eq!(calculate_p2(&parse(EXAMPLE_DATA_4)), Ok(3478));
     }
 
     #[test]
     fn test_p2_example_5() {
-        assert_eq!(calculate_p2(&parse(EXAMPLE_DATA_5)), 6474);
+        assert_eq!(calculate_p2(&parse(EXAMPLE_DATA_5)), Ok(6474));
     }
 
     #[test]
     fn test_p2_example_6() {
-        assert_eq!(calculate_p2(&parse(EXAMPLE_DATA_6)), 1140);
+        assert_eq!(calculate_p2(&parse(EXAMPLE_DATA_6)), Ok(1140));
     }
 
     #[test]
     fn test_p2_real() {
-        assert_eq!(calculate_p2(&parse(REAL_DATA)), 51041);
+        assert_eq!(calculate_p2(&parse(REAL_DATA)), Ok(51041));
     }
 
     #[test]
     fn test_p2_real_2() {
-        assert_eq!(calculate_p2(&parse(REAL_DATA_2)), 62958);
+        assert_eq!(calculate_p2(&parse(REAL_DATA_2)), Ok(62958));
     }
 }
@@ -1,36 +1,62 @@
 use advent_of_code_2018::bitvec_set::BitVecSet2D;
 use advent_of_code_2018::{Cli, Parser};
-use itertools::Itertools;
 use std::collections::VecDeque;
+use std::fmt;
 use std::fs;
+use std::num::ParseIntError;
 use std::str::FromStr;
 
+const SPRING: (i64, i64) = (0, 500);
+
+// Keeps each of a reservoir's sets to 128MiB.
+const MAX_AREA: i128 = 1 << 30;
+
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
 struct Vein {
-    start_x: usize,
-    stop_x: usize,
-    start_y: usize,
-    stop_y: usize,
+    start_x: i64,
+    stop_x: i64,
+    start_y: i64,
+    stop_y: i64,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+enum VeinError {
+    Malformed,
+    Number(ParseIntError),
+    Inverted,
+}
+
+impl fmt::Display for VeinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VeinError::Malformed => write!(f, "expected x=a, y=b..c or y=a, x=b..c"),
+            VeinError::Number(e) => write!(f, "{e}"),
+            VeinError::Inverted => write!(f, "range ends before it starts"),
+        }
+    }
 }
 
 impl FromStr for Vein {
-    type Err = ();
+    type Err = VeinError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (head, tail) = s.split_once(", ").ok_or(())?;
+        let (head, tail) = s.split_once(", ").ok_or(VeinError::Malformed)?;
 
-        let lhs = &head[2..];
-        let rhs = &tail[2..];
-        let lhs_value: usize = lhs.parse().map_err(|_| ())?;
-        let rhs_values: (usize, usize) = rhs
-            .split("..")
-            .filter_map(|x| x.parse().ok())
-            .collect_tuple()
-            .ok_or(())?;
+        let lhs = head.get(2..).ok_or(VeinError::Malformed)?;
+        let rhs = tail.get(2..).ok_or(VeinError::Malformed)?;
+        let lhs_value: i64 = lhs.parse().map_err(VeinError::Number)?;
+        let (lo, hi) = rhs.split_once("..").ok_or(VeinError::Malformed)?;
+        let rhs_values: (i64, i64) = (
+            lo.parse().map_err(VeinError::Number)?,
+            hi.parse().map_err(VeinError::Number)?,
+        );
+        if rhs_values.0 > rhs_values.1 {
+            return Err(VeinError::Inverted);
+        }
 
         let (start_x, stop_x, start_y, stop_y) = match head.bytes().next() {
             Some(b'x') => Ok((lhs_value, lhs_value, rhs_values.0, rhs_values.1)),
             Some(b'y') => Ok((rhs_values.0, rhs_values.1, lhs_value, lhs_value)),
-            _ => Err(()),
+            _ => Err(VeinError::Malformed),
         }?;
 
         Ok(Vein {
@@ -43,47 +69,70 @@ impl FromStr for Vein {
 }
 
 impl Vein {
-    fn points(&self) -> Vec<(usize, usize)> {
-        let mut ans = vec![];
-        for y in self.start_y..=self.stop_y {
-            for x in self.start_x..=self.stop_x {
-                ans.push((y, x));
-            }
-        }
-        ans
+    fn is_empty(&self) -> bool {
+        self.start_x > self.stop_x || self.start_y > self.stop_y
+    }
+
+    fn points(&self) -> impl Iterator<Item = (i64, i64)> + use<> {
+        let xs = self.start_x..=self.stop_x;
+        (self.start_y..=self.stop_y).flat_map(move |y| xs.clone().map(move |x| (y, x)))
     }
 }
 
-fn parse(raw_inp: &str) -> BitVecSet2D {
-    let points: Vec<_> = raw_inp
+fn parse(raw_inp: &str) -> Result<Vec<Vein>, ReservoirError> {
+    raw_inp
         .lines()
-        .filter_map(|line| line.parse::<Vein>().ok())
-        .flat_map(|v| v.points())
-        .collect();
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            line.trim()
+                .parse::<Vein>()
+                .map_err(|cause| ReservoirError::BadVein { line: i + 1, cause })
+        })
+        .collect()
+}
 
-    let max_x = points.iter().map(|(_, x)| x).max().expect("non-empty");
-    let max_y = points.iter().map(|(y, _)| y).max().expect("non-empty");
+#[derive(PartialEq, Eq, Debug)]
+enum ReservoirError {
+    BadVein { line: usize, cause: VeinError },
+    NoClay,
+    OutOfRange { y: i64, x: i64 },
+    TooLarge { height: i128, width: i128 },
+}
 
-    let mut result = BitVecSet2D::new((max_y + 2, max_x + 2));
-    points.iter().for_each(|&pt| {
-        result.insert(pt);
-    });
-    result
+impl fmt::Display for ReservoirError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReservoirError::BadVein { line, cause } => write!(f, "line {line}: {cause}"),
+            ReservoirError::NoClay => write!(f, "the scan has no clay"),
+            ReservoirError::OutOfRange { y, x } => {
+                write!(
+                    f,
+                    "x={x}, y={y} is too close to the edge of the coordinate range"
+                )
+            }
+            ReservoirError::TooLarge { height, width } => {
+                write!(f, "a {width}x{height} scan is too large to simulate")
+            }
+        }
+    }
 }
 
 enum NextState {
-    Wall((usize, usize)),
-    Drop((usize, usize)),
+    Wall((i64, i64)),
+    Drop((i64, i64)),
 }
 
-fn next<const DIR: isize>(
-    walls: &BitVecSet2D,
-    settled_water: &BitVecSet2D,
-    w: (usize, usize),
+// The reservoir's margin stops this before it leaves the grid.
+fn next<const DIR: i64>(
+    walls: &BitVecSet2D<i64>,
+    settled_water: &BitVecSet2D<i64>,
+    w: (i64, i64),
 ) -> NextState {
     let mut x = w.1;
     loop {
-        x = x.checked_add_signed(DIR).expect("adding dir overflowed");
+        x += DIR;
+        debug_assert!(walls.get(&(w.0, x)).is_some(), "water left the grid");
         if walls.contains(&(w.0, x)) {
             return NextState::Wall((w.0, x));
         }
@@ -94,94 +143,453 @@ fn next<const DIR: isize>(
     }
 }
 
-fn calculate(walls: &BitVecSet2D) -> (usize, usize) {
-    let (min_y, max_y) = walls
-        .iter()
-        .map(|(y, _)| y)
-        .minmax()
-        .into_option()
-        .expect("nonempty");
-
-    let mut wet = BitVecSet2D::new(walls.bounds);
-    let mut settled_water = BitVecSet2D::new(walls.bounds);
+struct Reservoir {
+    clay: BitVecSet2D<i64>,
+    springs: Vec<(i64, i64)>,
+    wet: BitVecSet2D<i64>,
+    settled: BitVecSet2D<i64>,
+    // Only rows spanned by clay are counted.
+    min_y: i64,
+    max_y: i64,
+}
 
-    let mut sources = VecDeque::default();
-    sources.push_back((min_y, 500));
+impl Reservoir {
+    // Springs are (y, x).
+    fn new(veins: &[Vein], springs: &[(i64, i64)]) -> Result<Reservoir, ReservoirError> {
+        // Check the area before listing any squares.
+        let veins = veins.iter().filter(|v| !v.is_empty()).collect::<Vec<_>>();
+        let (min_y, max_y) = veins
+            .iter()
+            .map(|v| v.start_y)
+            .min()
+            .zip(veins.iter().map(|v| v.stop_y).max())
+            .ok_or(ReservoirError::NoClay)?;
+        let (min_x, max_x) = veins
+            .iter()
+            .map(|v| (v.start_x, v.stop_x))
+            .chain(springs.iter().map(|&(_, x)| (x, x)))
+            .reduce(|(lo, hi), (start, stop)| (lo.min(start), hi.max(stop)))
+            .expect("non-empty");
+        let top = springs.iter().map(|(y, _)| *y).fold(min_y, i64::min);
 
-    while let Some(source) = sources.pop_front() {
-        let (sy, sx) = source;
-        if sy > max_y || settled_water.contains(&source) {
-            continue;
+        // Water spills one square past the clay on each side and below it.
+        let has_margin = |&(y, x): &(i64, i64)| x > i64::MIN && x < i64::MAX && y < i64::MAX;
+        if let Some((y, x)) = veins
+            .iter()
+            .flat_map(|v| [(v.start_y, v.start_x), (v.stop_y, v.stop_x)])
+            .chain(springs.iter().copied())
+            .find(|p| !has_margin(p))
+        {
+            return Err(ReservoirError::OutOfRange { y, x });
         }
-        wet.insert(source);
-        let down = (sy + 1, sx);
-        if !walls.contains(&down) && !settled_water.contains(&down) {
-            sources.push_back(down);
-            continue;
+        let (left, right, bottom) = (min_x - 1, max_x + 1, max_y + 1);
+
+        let height = i128::from(bottom) - i128::from(top) + 1;
+        let width = i128::from(right) - i128::from(left) + 1;
+        if height * width > MAX_AREA {
+            return Err(ReservoirError::TooLarge { height, width });
         }
+        let bounds = (height as usize, width as usize);
 
-        let left = next::<-1>(walls, &settled_water, (sy, sx));
-        let right = next::<1>(walls, &settled_water, (sy, sx));
+        let mut clay = BitVecSet2D::with_origin((top, left), bounds);
+        veins.iter().flat_map(|v| v.points()).for_each(|pt| {
+            clay.insert(pt);
+        });
 
-        match (left, right) {
-            (NextState::Wall(l), NextState::Wall(r)) => {
-                for x in l.1 + 1..=r.1 - 1 {
-                    wet.insert((sy, x));
-                    settled_water.insert((sy, x));
-                }
-                sources.push_back((sy - 1, sx));
+        let mut reservoir = Reservoir {
+            wet: clay.empty_like(),
+            settled: clay.empty_like(),
+            clay,
+            springs: springs.to_vec(),
+            min_y,
+            max_y,
+        };
+        reservoir.fill(top);
+        Ok(reservoir)
+    }
+
+    fn fill(&mut self, top: i64) {
+        let walls = &self.clay;
+        let wet = &mut self.wet;
+        let settled_water = &mut self.settled;
+
+        let mut sources = self
+            .springs
+            .iter()
+            .map(|&(y, x)| (y + 1, x))
+            .collect::<VecDeque<_>>();
+
+        while let Some(source) = sources.pop_front() {
+            let (sy, sx) = source;
+            if sy < top || sy > self.max_y || settled_water.contains(&source) {
+                continue;
             }
-            (NextState::Wall(l), NextState::Drop(r)) => {
-                for x in l.1 + 1..=r.1 {
-                    wet.insert((sy, x));
-                }
-                sources.push_back(r);
+            wet.insert(source);
+            let down = (sy + 1, sx);
+            if !walls.contains(&down) && !settled_water.contains(&down) {
+                sources.push_back(down);
+                continue;
             }
-            (NextState::Drop(l), NextState::Wall(r)) => {
-                for x in l.1..=r.1 - 1 {
-                    wet.insert((sy, x));
+
+            let left = next::<-1>(walls, settled_water, (sy, sx));
+            let right = next::<1>(walls, settled_water, (sy, sx));
+
+            match (left, right) {
+                (NextState::Wall(l), NextState::Wall(r)) => {
+                    // Every stream falling into this row can now spread.
+                    for x in l.1 + 1..=r.1 - 1 {
+                        wet.insert((sy, x));
+                        settled_water.insert((sy, x));
+                        if wet.contains(&(sy - 1, x)) {
+                            sources.push_back((sy - 1, x));
+                        }
+                    }
                 }
-                sources.push_back(l);
-            }
-            (NextState::Drop(l), NextState::Drop(r)) => {
-                for x in l.1..=r.1 {
-                    wet.insert((sy, x));
+                (NextState::Wall(l), NextState::Drop(r)) => {
+                    for x in l.1 + 1..=r.1 {
+                        wet.insert((sy, x));
+                    }
+                    sources.push_back(r);
+                }
+                (NextState::Drop(l), NextState::Wall(r)) => {
+                    for x in l.1..=r.1 - 1 {
+                        wet.insert((sy, x));
+                    }
+                    sources.push_back(l);
+                }
+                (NextState::Drop(l), NextState::Drop(r)) => {
+                    for x in l.1..=r.1 {
+                        wet.insert((sy, x));
+                    }
+                    sources.push_back(l);
+                    sources.push_back(r);
                 }
-                sources.push_back(l);
-                sources.push_back(r);
             }
         }
     }
 
-    let p1 = wet.len();
-    let p2 = settled_water.len();
-    (p1, p2)
+    fn wet(&self) -> &BitVecSet2D<i64> {
+        &self.wet
+    }
+
+    fn settled(&self) -> &BitVecSet2D<i64> {
+        &self.settled
+    }
+
+    fn counts(&self) -> (usize, usize) {
+        let (_, left) = self.clay.origin;
+        let right = left + self.clay.bounds.1 as i64 - 1;
+        let min = (self.min_y, left);
+        let max = (self.max_y, right);
+        (
+            self.wet().count_in_rect(min, max),
+            self.settled().count_in_rect(min, max),
+        )
+    }
+
+    fn render(&self) -> String {
+        let (top, left) = self.clay.origin;
+        let (height, width) = self.clay.bounds;
+
+        (top..top + height as i64 - 1)
+            .map(|y| {
+                (left..left + width as i64)
+                    .map(|x| {
+                        let pos = (y, x);
+                        if self.springs.contains(&pos) {
+                            '+'
+                        } else if self.clay.contains(&pos) {
+                            '#'
+                        } else if self.settled.contains(&pos) {
+                            '~'
+                        } else if self.wet.contains(&pos) {
+                            '|'
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect::<String>()
+                    + "\n"
+            })
+            .collect()
+    }
+}
+
+fn calculate(veins: &[Vein], springs: &[(i64, i64)]) -> (usize, usize) {
+    Reservoir::new(veins, springs)
+        .unwrap_or_else(|e| panic!("{e}"))
+        .counts()
+}
+
+fn parse_spring(s: &str) -> Result<(i64, i64), String> {
+    let (x, y) = s
+        .split_once(',')
+        .ok_or_else(|| format!("expected x,y but got {s}"))?;
+    let parse = |v: &str| v.trim().parse::<i64>().map_err(|e| e.to_string());
+    Ok((parse(y)?, parse(x)?))
+}
+
+#[derive(Parser)]
+struct Day17Cli {
+    #[clap(flatten)]
+    cli: Cli,
+
+    /// Draw the reservoir after the water has settled instead of printing
+    /// the answers.
+    #[clap(long)]
+    render: bool,
+
+    /// Position of a spring as x,y. May be repeated; defaults to 500,0.
+    #[clap(long, value_parser = parse_spring)]
+    spring: Vec<(i64, i64)>,
 }
 
 fn main() {
-    let args = Cli::parse();
+    let args = Day17Cli::parse();
 
-    let inp = fs::read_to_string(args.input).expect("can't open input file");
+    let inp = fs::read_to_string(&args.cli.input).expect("can't open input file");
 
-    let data = parse(&inp);
-    let (p1, p2) = calculate(&data);
+    let data = parse(&inp).unwrap_or_else(|e| panic!("{e}"));
+    let springs = if args.spring.is_empty() {
+        vec![SPRING]
+    } else {
+        args.spring
+    };
+
+    if args.render {
+        let reservoir = Reservoir::new(&data, &springs).unwrap_or_else(|e| panic!("{e}"));
+        print!("{}", reservoir.render());
+        return;
+    }
+
+    let (p1, p2) = calculate(&data, &springs);
     println!("{p1}\n{p2}");
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ahash::AHashSet;
 
     const EXAMPLE_DATA: &str = include_str!("../../inputs/examples/2018_17");
     const REAL_DATA: &str = include_str!("../../inputs/real/2018_17");
 
     #[test]
     fn test_example() {
-        assert_eq!(calculate(&parse(EXAMPLE_DATA)), (57, 29));
+        assert_eq!(
+            calculate(&parse(EXAMPLE_DATA).unwrap(), &[SPRING]),
+            (57, 29)
+        );
+    }
+
+    #[test]
+    fn test_render_example() {
+        let reservoir =
+            Reservoir::new(&parse(EXAMPLE_DATA).unwrap(), &[SPRING]).expect("valid scan");
+        assert_eq!(
+            reservoir.render(),
+            "\
+......+.......
+......|.....#.
+.#..#||||...#.
+.#..#~~#|.....
+.#..#~~#|.....
+.#~~~~~#|.....
+.#~~~~~#|.....
+.#######|.....
+........|.....
+...|||||||||..
+...|#~~~~~#|..
+...|#~~~~~#|..
+...|#~~~~~#|..
+...|#######|..
+"
+        );
+    }
+
+    #[test]
+    fn test_multiple_springs() {
+        // A second spring beyond the clay only adds a single stream.
+        let veins = parse(EXAMPLE_DATA).unwrap();
+        let reservoir = Reservoir::new(&veins, &[SPRING, (0, 510)]).expect("valid scan");
+        assert_eq!(reservoir.counts(), (57 + 13, 29));
+        assert!(reservoir.wet().contains(&(13, 510)));
+        assert_eq!(reservoir.settled().len(), 29);
+    }
+
+    type Squares = AHashSet<(i64, i64)>;
+
+    // Applies the flow rules everywhere until nothing changes.
+    fn reference_water(reservoir: &Reservoir) -> (Squares, Squares) {
+        let clay = |pos: &(i64, i64)| reservoir.clay.contains(pos);
+        let (top, left) = reservoir.clay.origin;
+        let right = left + reservoir.clay.bounds.1 as i64 - 1;
+        let bottom = reservoir.max_y;
+
+        let mut wet = reservoir
+            .springs
+            .iter()
+            .map(|&(y, x)| (y + 1, x))
+            .filter(|&(y, _)| y <= bottom)
+            .collect::<AHashSet<_>>();
+        let mut settled = AHashSet::default();
+        loop {
+            let mut next_wet = wet.clone();
+            for &(y, x) in wet.difference(&settled) {
+                let below = (y + 1, x);
+                if y == bottom {
+                    continue;
+                }
+                if !clay(&below) && !settled.contains(&below) {
+                    next_wet.insert(below);
+                } else {
+                    next_wet.extend([(y, x - 1), (y, x + 1)].into_iter().filter(|p| !clay(p)));
+                }
+            }
+
+            let mut next_settled = settled.clone();
+            for y in top..=bottom {
+                let mut x = left;
+                while x <= right {
+                    if !next_wet.contains(&(y, x)) {
+                        x += 1;
+                        continue;
+                    }
+                    let start = x;
+                    while next_wet.contains(&(y, x + 1)) {
+                        x += 1;
+                    }
+                    let enclosed = clay(&(y, start - 1)) && clay(&(y, x + 1));
+                    let supported =
+                        (start..=x).all(|x| clay(&(y + 1, x)) || settled.contains(&(y + 1, x)));
+                    if enclosed && supported {
+                        next_settled.extend((start..=x).map(|x| (y, x)));
+                    }
+                    x += 1;
+                }
+            }
+
+            if next_wet == wet && next_settled == settled {
+                return (wet, settled);
+            }
+            (wet, settled) = (next_wet, next_settled);
+        }
+    }
+
+    #[test]
+    fn test_matches_reference() {
+        let mut rng = fastrand::Rng::with_seed(17);
+        // Keep clay two rows below the springs, as in the puzzle.
+        for scan in 0..100 {
+            let mut raw = String::new();
+            for _ in 0..rng.usize(1..=6) {
+                // A cup, with walls of random heights.
+                let (a, b) = (rng.i64(490..505), rng.i64(495..510));
+                let (x0, x1) = (a.min(b), a.max(b));
+                let floor = rng.i64(8..30);
+                let (h0, h1) = (rng.i64(1..=6), rng.i64(1..=6));
+                raw += &format!("x={x0}, y={}..{floor}\n", floor - h0);
+                raw += &format!("x={x1}, y={}..{floor}\n", floor - h1);
+                raw += &format!("y={floor}, x={x0}..{x1}\n");
+            }
+            for _ in 0..rng.usize(..8) {
+                let (x, y) = (rng.i64(488..513), rng.i64(2..32));
+                raw += &match rng.bool() {
+                    true => format!("x={x}, y={y}..{}\n", y + rng.i64(0..4)),
+                    false => format!("y={y}, x={x}..{}\n", x + rng.i64(0..6)),
+                };
+            }
+            let springs = match scan % 3 {
+                0 => vec![SPRING, (0, rng.i64(490..510))],
+                _ => vec![SPRING],
+            };
+
+            let reservoir = Reservoir::new(&parse(&raw).unwrap(), &springs).expect("valid scan");
+            let in_rows = |set: &BitVecSet2D<i64>| {
+                set.iter()
+                    .filter(|&(y, _)| y <= reservoir.max_y)
+                    .collect::<AHashSet<_>>()
+            };
+            let (wet, settled) = reference_water(&reservoir);
+            assert_eq!(in_rows(reservoir.wet()), wet, "scan {scan}:\n{raw}");
+            assert_eq!(in_rows(reservoir.settled()), settled, "scan {scan}:\n{raw}");
+        }
+    }
+
+    #[test]
+    fn test_spill_past_zero() {
+        let veins = parse("x=0, y=2..4\ny=4, x=0..3\nx=3, y=2..4\n").unwrap();
+        let reservoir = Reservoir::new(&veins, &[(0, 1)]).expect("valid scan");
+        assert_eq!(
+            reservoir.render(),
+            "\
+..+...
+||||||
+|#~~#|
+|#~~#|
+|####|
+"
+        );
+        assert_eq!(reservoir.counts(), (10, 4));
+    }
+
+    #[test]
+    fn test_invalid_scans() {
+        assert_eq!(
+            Reservoir::new(&[], &[SPRING]).err(),
+            Some(ReservoirError::NoClay)
+        );
+        let edge = parse(&format!("x={}, y=1..2", i64::MAX)).unwrap();
+        assert_eq!(
+            Reservoir::new(&edge, &[SPRING]).err(),
+            Some(ReservoirError::OutOfRange { y: 1, x: i64::MAX })
+        );
+        let wide = parse("x=0, y=1..2\nx=100000000000, y=1..2").unwrap();
+        assert!(matches!(
+            Reservoir::new(&wide, &[SPRING]),
+            Err(ReservoirError::TooLarge { .. })
+        ));
+        let long = parse("y=1, x=0..100000000000").unwrap();
+        assert_eq!(
+            Reservoir::new(&long, &[SPRING]).err(),
+            Some(ReservoirError::TooLarge {
+                height: 3,
+                width: 100000000003
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid_veins() {
+        assert_eq!(
+            parse("x=5, y=1..2\nx=5, y=2..1").err(),
+            Some(ReservoirError::BadVein {
+                line: 2,
+                cause: VeinError::Inverted
+            })
+        );
+        let overflow = "99999999999999999999".parse::<i64>().unwrap_err();
+        assert_eq!(
+            parse("y=1, x=0..99999999999999999999").err(),
+            Some(ReservoirError::BadVein {
+                line: 1,
+                cause: VeinError::Number(overflow)
+            })
+        );
+        assert_eq!(
+            parse("x=5 y=1..2").err(),
+            Some(ReservoirError::BadVein {
+                line: 1,
+                cause: VeinError::Malformed
+            })
+        );
     }
 
     #[test]
     fn test_real() {
-        assert_eq!(calculate(&parse(REAL_DATA)), (27736, 22474));
+        assert_eq!(
+            calculate(&parse(REAL_DATA).unwrap(), &[SPRING]),
+            (27736, 22474)
+        );
     }
 }
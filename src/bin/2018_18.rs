@@ -1,6 +1,7 @@
+use advent_of_code_2018::automaton::{Automaton2D, MOORE, RuleTable, Transition};
+use advent_of_code_2018::cycle::find_cycle_by_state;
 use advent_of_code_2018::grid_util::make_byte_grid;
 use advent_of_code_2018::{Cli, Parser};
-use ahash::AHashMap;
 use ndarray::Array2;
 use std::fs;
 
@@ -8,74 +9,59 @@ fn parse(raw_inp: &str) -> Array2<u8> {
     make_byte_grid(raw_inp)
 }
 
-const NEIGHBOURS: [(isize, isize); 8] = [
-    (-1, -1),
-    (-1, 0),
-    (-1, 1),
-    (0, -1),
-    (0, 1),
-    (1, -1),
-    (1, 0),
-    (1, 1),
-];
+const TREES: u8 = b'|';
+const LUMBERYARD: u8 = b'#';
+const OPEN: u8 = b'.';
+
+// Neighbour counts are (trees, lumberyards).
+const LUMBER_RULES: RuleTable<2> = RuleTable {
+    transitions: &[
+        Transition {
+            from: OPEN,
+            to: TREES,
+            counts: [3..=8, 0..=8],
+        },
+        Transition {
+            from: TREES,
+            to: LUMBERYARD,
+            counts: [0..=8, 3..=8],
+        },
+        Transition {
+            from: LUMBERYARD,
+            to: OPEN,
+            counts: [0..=0, 0..=8],
+        },
+        Transition {
+            from: LUMBERYARD,
+            to: OPEN,
+            counts: [0..=8, 0..=0],
+        },
+    ],
+};
+
+const AUTOMATON: Automaton2D<2, RuleTable<2>> = Automaton2D {
+    neighbourhood: &MOORE,
+    tracked: [TREES, LUMBERYARD],
+    rule: LUMBER_RULES,
+};
 
 fn grid_to_score(data: &Array2<u8>) -> usize {
-    data.iter().filter(|&e| e == &b'|').count() * data.iter().filter(|&e| e == &b'#').count()
+    data.iter().filter(|&e| e == &TREES).count() * data.iter().filter(|&e| e == &LUMBERYARD).count()
 }
 
 fn calculate<const ENABLE_P2: bool>(data: &Array2<u8>) -> (usize, usize) {
-    let mut data = data.clone();
-    let mut p1 = 0;
-    let mut p2 = 0;
-
-    let mut states = AHashMap::default();
-
-    for s in 1.. {
-        let mut next_data = data.clone();
-        next_data.indexed_iter_mut().for_each(|((y, x), e)| {
-            let mut trees = 0;
-            let mut lumberyards = 0;
-            for (ny, nx) in NEIGHBOURS.into_iter() {
-                match data.get((y.wrapping_add_signed(ny), x.wrapping_add_signed(nx))) {
-                    Some(b'|') => trees += 1,
-                    Some(b'#') => lumberyards += 1,
-                    _ => {}
-                }
-            }
-            if *e == b'.' && trees >= 3 {
-                *e = b'|';
-            } else if *e == b'|' && lumberyards >= 3 {
-                *e = b'#';
-            } else if *e == b'#' && (lumberyards == 0 || trees == 0) {
-                *e = b'.';
-            } else {
-                *e = data[(y, x)];
-            }
-        });
-        std::mem::swap(&mut data, &mut next_data);
-        std::mem::drop(next_data);
-
-        if s == 10 {
-            p1 = grid_to_score(&data);
-            if !ENABLE_P2 {
-                break;
-            }
-        }
-
-        if let Some(old_value) = states.insert(data.clone(), s) {
-            let cycle_len = s - old_value;
-            let p2_iter = (1000000000 - s) % cycle_len;
-            let p2_grid = states
-                .iter()
-                .filter(|&(_, v)| *v == old_value + p2_iter)
-                .map(|(k, _)| k)
-                .next()
-                .unwrap();
-            p2 = grid_to_score(p2_grid);
-            break;
-        }
+    if !ENABLE_P2 {
+        let mut p1_grid = data.clone();
+        AUTOMATON.run(&mut p1_grid, 10);
+        return (grid_to_score(&p1_grid), 0);
     }
 
+    // Each generation is allocated once, as its entry in the history.
+    let (cycle, history) = find_cycle_by_state(data.clone(), |grid| AUTOMATON.next(grid));
+
+    let p1 = grid_to_score(&history[cycle.equivalent_generation(10)]);
+    let p2 = grid_to_score(&history[cycle.equivalent_generation(1000000000)]);
+
     (p1, p2)
 }
 
@@ -99,6 +85,7 @@ mod tests {
     #[test]
     fn test_example() {
         assert_eq!(calculate::<false>(&parse(EXAMPLE_DATA)).0, 1147);
+        assert_eq!(calculate::<true>(&parse(EXAMPLE_DATA)), (1147, 0));
     }
 
     #[test]
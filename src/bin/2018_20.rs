@@ -1,119 +1,357 @@
 use advent_of_code_2018::{Cli, Parser};
 use ahash::{AHashMap, AHashSet};
-use std::{collections::VecDeque, fs};
+use itertools::Itertools;
+use ndarray::Array2;
+use std::iter::Peekable;
+use std::str::Bytes;
+use std::{collections::VecDeque, fmt, fs};
 
-#[derive(Debug)]
+type Room = (i32, i32);
+
+const ORIGIN: Room = (0, 0);
+
+// Steps are (dy, dx).
+const DIRECTIONS: [(u8, (i32, i32)); 4] = [
+    (b'N', (-1, 0)),
+    (b'E', (0, 1)),
+    (b'S', (1, 0)),
+    (b'W', (0, -1)),
+];
+
+fn step((y, x): Room, dir: usize) -> Room {
+    let (dy, dx) = DIRECTIONS[dir].1;
+    (y + dy, x + dx)
+}
+
+fn direction_of(letter: u8) -> usize {
+    DIRECTIONS
+        .iter()
+        .position(|&(l, _)| l == letter)
+        .unwrap_or_else(|| panic!("Unhandled direction {letter}"))
+}
+
+// Each room holds a bitmask of the DIRECTIONS which lead through a door.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RoomMap {
+    doors: AHashMap<Room, u8>,
+}
+
+impl RoomMap {
+    fn new() -> RoomMap {
+        let mut doors = AHashMap::default();
+        doors.insert(ORIGIN, 0);
+        RoomMap { doors }
+    }
+
+    fn from_regex(regex: &str) -> Result<RoomMap, RegexError> {
+        let mut map = RoomMap::new();
+        walk(validate(regex)?.as_bytes(), &mut map);
+        Ok(map)
+    }
+
+    fn add_door(&mut self, room: Room, dir: usize) -> Room {
+        let next = step(room, dir);
+        *self.doors.entry(room).or_default() |= 1 << dir;
+        *self.doors.entry(next).or_default() |= 1 << ((dir + 2) % 4);
+        next
+    }
+
+    fn rooms(&self) -> impl Iterator<Item = Room> + use<'_> {
+        self.doors.keys().copied()
+    }
+
+    fn neighbours(&self, room: Room) -> impl Iterator<Item = Room> + use<> {
+        let doors = self.doors.get(&room).copied().unwrap_or(0);
+        (0..DIRECTIONS.len())
+            .filter(move |dir| doors & (1 << dir) != 0)
+            .map(move |dir| step(room, dir))
+    }
+
+    // Each room's distance and the room it was reached from.
+    fn search(&self, start: Room) -> AHashMap<Room, (usize, Room)> {
+        let mut seen = AHashMap::default();
+        let mut q = VecDeque::default();
+        seen.insert(start, (0, start));
+        q.push_back((start, 0));
+
+        while let Some((room, dist)) = q.pop_front() {
+            for next in self.neighbours(room) {
+                if !seen.contains_key(&next) {
+                    seen.insert(next, (dist + 1, room));
+                    q.push_back((next, dist + 1));
+                }
+            }
+        }
+        seen
+    }
+
+    fn distances(&self) -> AHashMap<Room, usize> {
+        self.search(ORIGIN)
+            .into_iter()
+            .map(|(room, (dist, _))| (room, dist))
+            .collect()
+    }
+
+    fn shortest_path(&self, from: Room, to: Room) -> Option<Vec<Room>> {
+        let seen = self.search(from);
+        seen.get(&to)?;
+
+        let mut path = vec![to];
+        while let Some(&room) = path.last()
+            && room != from
+        {
+            path.push(seen[&room].1);
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    // Ties go to the first room in reading order.
+    fn farthest_room(&self) -> (Room, usize) {
+        self.distances()
+            .into_iter()
+            .max_by_key(|&((y, x), dist)| (dist, -y, -x))
+            .expect("origin is always present")
+    }
+
+    fn render(&self) -> String {
+        let (min_y, max_y) = self
+            .rooms()
+            .map(|(y, _)| y)
+            .minmax()
+            .into_option()
+            .expect("nonempty");
+        let (min_x, max_x) = self
+            .rooms()
+            .map(|(_, x)| x)
+            .minmax()
+            .into_option()
+            .expect("nonempty");
+        let height = 2 * (max_y - min_y + 1) + 1;
+        let width = 2 * (max_x - min_x + 1) + 1;
+
+        let mut grid = Array2::from_elem((height as usize, width as usize), b'#');
+        for (&(y, x), &doors) in &self.doors {
+            let centre = (2 * (y - min_y) + 1, 2 * (x - min_x) + 1);
+            let cell = |(y, x): (i32, i32)| (y as usize, x as usize);
+            grid[cell(centre)] = if (y, x) == ORIGIN { b'X' } else { b'.' };
+            for (dir, &(_, (dy, dx))) in DIRECTIONS.iter().enumerate() {
+                if doors & (1 << dir) != 0 {
+                    grid[cell((centre.0 + dy, centre.1 + dx))] = if dy == 0 { b'|' } else { b'-' };
+                }
+            }
+        }
+
+        grid.outer_iter()
+            .map(|row| row.iter().map(|&c| c as char).collect::<String>() + "\n")
+            .collect()
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
 enum PathElement {
     Route(u8),
     Branch(Vec<Vec<PathElement>>),
 }
 
-fn travelled_squares(data: &[PathElement], mut y: i32, mut x: i32) -> Vec<(i32, i32)> {
-    let mut ans = Vec::default();
-    ans.push((0, 0));
-
-    data.iter().for_each(|elem| match elem {
-        PathElement::Route(dir) => {
-            let (dy, dx) = match dir {
-                b'N' => (-1, 0),
-                b'E' => (0, 1),
-                b'S' => (1, 0),
-                b'W' => (0, -1),
-                x => panic!("Unhandled direction {x}"),
-            };
-            ans.push((y + dy, x + dx));
-            ans.push((y + 2 * dy, x + 2 * dx));
-            y += 2 * dy;
-            x += 2 * dx;
+// Positions are byte offsets into the regex.
+#[derive(PartialEq, Eq, Debug)]
+enum RegexError {
+    MissingStart,
+    MissingEnd,
+    UnexpectedChar { pos: usize, found: char },
+    UnclosedGroup { pos: usize },
+    UnmatchedClose { pos: usize },
+    AlternativeOutsideGroup { pos: usize },
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegexError::MissingStart => write!(f, "regex doesn't start with ^"),
+            RegexError::MissingEnd => write!(f, "regex doesn't end with $"),
+            RegexError::UnexpectedChar { pos, found } => {
+                write!(f, "unexpected {found:?} at position {pos}")
+            }
+            RegexError::UnclosedGroup { pos } => {
+                write!(f, "group opened at position {pos} is never closed")
+            }
+            RegexError::UnmatchedClose { pos } => {
+                write!(f, "unmatched ) at position {pos}")
+            }
+            RegexError::AlternativeOutsideGroup { pos } => {
+                write!(f, "| at position {pos} is outside any group")
+            }
         }
-        PathElement::Branch(branches) => {
-            branches.iter().for_each(|branch| {
-                ans.extend(travelled_squares(branch, y, x));
-            });
+    }
+}
+
+fn validate(regex: &str) -> Result<&str, RegexError> {
+    let body = regex
+        .strip_prefix('^')
+        .ok_or(RegexError::MissingStart)?
+        .strip_suffix('$')
+        .ok_or(RegexError::MissingEnd)?;
+
+    let mut open = vec![];
+    for (i, c) in body.char_indices() {
+        let pos = i + 1;
+        match c {
+            'N' | 'E' | 'S' | 'W' => {}
+            '(' => open.push(pos),
+            ')' => {
+                open.pop().ok_or(RegexError::UnmatchedClose { pos })?;
+            }
+            '|' if open.is_empty() => return Err(RegexError::AlternativeOutsideGroup { pos }),
+            '|' => {}
+            found => return Err(RegexError::UnexpectedChar { pos, found }),
         }
-    });
+    }
+
+    match open.last() {
+        Some(&pos) => Err(RegexError::UnclosedGroup { pos }),
+        None => Ok(body),
+    }
+}
 
-    ans
+fn parse_regex(regex: &str) -> Result<Vec<PathElement>, RegexError> {
+    let body = validate(regex)?;
+    Ok(make_path(&mut body.bytes().peekable()))
 }
 
-fn make_path(regex: &mut VecDeque<u8>) -> Vec<PathElement> {
+fn make_path(regex: &mut Peekable<Bytes>) -> Vec<PathElement> {
     let mut path = vec![];
 
-    while let Some(item) = regex.pop_front() {
+    while let Some(&item) = regex.peek() {
         match item {
             b'(' => {
-                let mut subpaths = vec![];
-                subpaths.push(make_path(regex));
-                while let Some(b'|') = regex.pop_front() {
+                regex.next();
+                let mut subpaths = vec![make_path(regex)];
+                while let Some(b'|') = regex.next() {
                     subpaths.push(make_path(regex));
                 }
                 path.push(PathElement::Branch(subpaths));
             }
-            b'N' | b'E' | b'W' | b'S' => path.push(PathElement::Route(item)),
-            other => {
-                regex.push_front(other);
-                break;
+            b'|' | b')' => break,
+            dir => {
+                regex.next();
+                path.push(PathElement::Route(dir));
             }
         }
     }
     path
 }
 
-fn flood_fill(grid: &AHashSet<(i32, i32)>) -> AHashMap<(i32, i32), i32> {
-    let mut costs = AHashMap::default();
-    let mut q = VecDeque::default();
-    q.push_back((0, 0, 0)); // y, x, cost
-
-    while let Some((y, x, cost)) = q.pop_front() {
-        if let Some(&prev_cost) = costs.get(&(y, x))
-            && prev_cost <= cost
-        {
-            continue;
+fn write_path(path: &[PathElement], out: &mut String) {
+    for elem in path {
+        match elem {
+            PathElement::Route(dir) => out.push(*dir as char),
+            PathElement::Branch(branches) => {
+                out.push('(');
+                for (i, branch) in branches.iter().enumerate() {
+                    if i > 0 {
+                        out.push('|');
+                    }
+                    write_path(branch, out);
+                }
+                out.push(')');
+            }
         }
+    }
+}
+
+fn to_regex(path: &[PathElement]) -> String {
+    let mut out = String::from("^");
+    write_path(path, &mut out);
+    out.push('$');
+    out
+}
 
-        costs.insert((y, x), cost);
+// Tracks the rooms the routes so far could end in, so takes time proportional
+// to the regex's length times the size of that set.
+fn walk(regex: &[u8], map: &mut RoomMap) {
+    let mut current = AHashSet::from_iter([ORIGIN]);
+    let mut groups: Vec<(AHashSet<Room>, AHashSet<Room>)> = vec![];
 
-        if grid.contains(&(y + 1, x)) {
-            q.push_back((y + 2, x, cost + 1));
-        }
-        if grid.contains(&(y - 1, x)) {
-            q.push_back((y - 2, x, cost + 1));
-        }
-        if grid.contains(&(y, x + 1)) {
-            q.push_back((y, x + 2, cost + 1));
-        }
-        if grid.contains(&(y, x - 1)) {
-            q.push_back((y, x - 2, cost + 1));
+    for &c in regex {
+        match c {
+            b'(' => groups.push((current.clone(), AHashSet::default())),
+            b'|' => {
+                let (starts, ends) = groups.last_mut().expect("unbalanced |");
+                ends.extend(current.drain());
+                current.clone_from(starts);
+            }
+            b')' => {
+                let (_, ends) = groups.pop().expect("unbalanced )");
+                current.extend(ends);
+            }
+            letter => {
+                let dir = direction_of(letter);
+                current = current
+                    .into_iter()
+                    .map(|room| map.add_door(room, dir))
+                    .collect();
+            }
         }
     }
+}
 
-    costs
+fn parse(raw_inp: &str) -> RoomMap {
+    RoomMap::from_regex(raw_inp.trim()).unwrap_or_else(|e| panic!("{e}"))
 }
 
-fn parse(raw_inp: &str) -> AHashMap<(i32, i32), i32> {
-    let mut inp: VecDeque<u8> = raw_inp.trim().bytes().collect();
-    inp.pop_front(); // ^
-    inp.pop_back(); // $
-    let grid = travelled_squares(&make_path(&mut inp), 0, 0);
-    flood_fill(&grid.into_iter().collect::<AHashSet<_>>())
+fn calculate_p1(map: &RoomMap) -> usize {
+    map.farthest_room().1
 }
 
-fn calculate_p1(costs: &AHashMap<(i32, i32), i32>) -> i32 {
-    *costs.values().max().unwrap_or(&0)
+fn calculate_p2(map: &RoomMap) -> usize {
+    map.distances().values().filter(|&n| *n >= 1000).count()
 }
 
-fn calculate_p2(costs: &AHashMap<(i32, i32), i32>) -> usize {
-    costs.values().filter(|&n| *n >= 1000).count()
+#[derive(Parser)]
+struct Day20Cli {
+    #[clap(flatten)]
+    cli: Cli,
+
+    /// Draw the map of the facility instead of printing the answers.
+    #[clap(long)]
+    render: bool,
+
+    /// Print the rooms, as x,y, along a shortest route to the farthest room
+    /// instead of the answers.
+    #[clap(long)]
+    route: bool,
+
+    /// Parse the regex and print it back out instead of the answers.
+    #[clap(long)]
+    print_regex: bool,
 }
 
 fn main() {
-    let args = Cli::parse();
+    let args = Day20Cli::parse();
+
+    let inp = fs::read_to_string(&args.cli.input).expect("can't open input file");
+
+    if args.print_regex {
+        let path = parse_regex(inp.trim()).unwrap_or_else(|e| panic!("{e}"));
+        println!("{}", to_regex(&path));
+        return;
+    }
 
-    let inp = fs::read_to_string(args.input).expect("can't open input file");
+    let map = parse(&inp);
+    if args.render {
+        print!("{}", map.render());
+        return;
+    }
+    if args.route {
+        let (farthest, _) = map.farthest_room();
+        let route = map.shortest_path(ORIGIN, farthest).expect("reachable");
+        route.iter().for_each(|(y, x)| println!("{x},{y}"));
+        return;
+    }
 
-    let nums = parse(&inp);
-    let p1 = calculate_p1(&nums);
-    let p2 = calculate_p2(&nums);
+    let p1 = calculate_p1(&map);
+    let p2 = calculate_p2(&map);
     println!("{p1}\n{p2}");
 }
 
@@ -129,6 +367,190 @@ mod tests {
         assert_eq!(calculate_p1(&parse(EXAMPLE_DATA)), 31);
     }
 
+    #[test]
+    fn test_p1_small_examples() {
+        assert_eq!(calculate_p1(&parse("^WNE$")), 3);
+        assert_eq!(calculate_p1(&parse("^ENWWW(NEEE|SSE(EE|N))$")), 10);
+        assert_eq!(
+            calculate_p1(&parse("^ENNWSWW(NEWS|)SSSEEN(WNSE|)EE(SWEN|)NNN$")),
+            18
+        );
+        assert_eq!(
+            calculate_p1(&parse(
+                "^ESSWWN(E|NNENN(EESS(WNSE|)SSS|WWWSSSSE(SW|NNNE)))$"
+            )),
+            23
+        );
+    }
+
+    #[test]
+    fn test_routes_continue_from_every_branch() {
+        let map = parse("^(N|S)E(NEWS|)$");
+        assert_eq!(map.rooms().count(), 9);
+        assert_eq!(map.farthest_room(), ((-2, 2), 4));
+        assert_eq!(
+            map.shortest_path(ORIGIN, (1, 1)),
+            Some(vec![(0, 0), (1, 0), (1, 1)])
+        );
+    }
+
+    #[test]
+    fn test_many_end_rooms() {
+        // Each group adds a possible end room, which later letters then move.
+        let map = parse(&format!("^{}$", "(N|S)".repeat(1000)));
+        assert_eq!(map.rooms().count(), 2001);
+        assert_eq!(map.farthest_room(), ((-1000, 0), 1000));
+
+        // So the map itself can be quadratic in the regex.
+        let map = parse(&format!("^{}{}$", "(N|S)".repeat(300), "E".repeat(300)));
+        assert_eq!(map.rooms().count(), 601 + 301 * 300);
+    }
+
+    fn expand(path: &[PathElement]) -> Vec<Vec<u8>> {
+        path.iter()
+            .fold(vec![vec![]], |routes, element| match element {
+                PathElement::Route(letter) => routes
+                    .into_iter()
+                    .map(|mut route| {
+                        route.push(*letter);
+                        route
+                    })
+                    .collect(),
+                PathElement::Branch(alternatives) => {
+                    let endings = alternatives.iter().flat_map(|a| expand(a)).collect_vec();
+                    routes
+                        .iter()
+                        .cartesian_product(&endings)
+                        .map(|(route, ending)| [route.as_slice(), ending].concat())
+                        .collect()
+                }
+            })
+    }
+
+    fn random_regex(rng: &mut fastrand::Rng, depth: usize) -> String {
+        (0..rng.usize(1..=3))
+            .map(|_| match rng.usize(..3) {
+                0 if depth > 0 => format!(
+                    "({})",
+                    (0..rng.usize(1..=3))
+                        .map(|_| match rng.usize(..4) {
+                            0 => String::new(),
+                            _ => random_regex(rng, depth - 1),
+                        })
+                        .join("|")
+                ),
+                _ => (0..rng.usize(1..=3))
+                    .map(|_| DIRECTIONS[rng.usize(..4)].0 as char)
+                    .collect(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_walk_matches_route_expansion() {
+        let mut rng = fastrand::Rng::with_seed(20);
+        for _ in 0..300 {
+            let regex = format!("^{}$", random_regex(&mut rng, 3));
+            let mut expected = RoomMap::new();
+            for route in expand(&parse_regex(&regex).expect("valid regex")) {
+                route.iter().fold(ORIGIN, |room, &letter| {
+                    expected.add_door(room, direction_of(letter))
+                });
+            }
+            assert_eq!(parse(&regex), expected, "{regex}");
+        }
+    }
+
+    #[test]
+    fn test_regex_round_trip() {
+        for regex in [
+            "^WNE$",
+            "^ENWWW(NEEE|SSE(EE|N))$",
+            "^ENNWSWW(NEWS|)SSSEEN(WNSE|)EE(SWEN|)NNN$",
+            EXAMPLE_DATA.trim(),
+        ] {
+            assert_eq!(to_regex(&parse_regex(regex).expect("valid regex")), regex);
+        }
+        assert_eq!(
+            parse_regex("^N(E|)$"),
+            Ok(vec![
+                PathElement::Route(b'N'),
+                PathElement::Branch(vec![vec![PathElement::Route(b'E')], vec![]]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_regex_errors() {
+        assert_eq!(parse_regex("NEWS$"), Err(RegexError::MissingStart));
+        assert_eq!(parse_regex("^NEWS"), Err(RegexError::MissingEnd));
+        assert_eq!(
+            parse_regex("^NE(S|W$"),
+            Err(RegexError::UnclosedGroup { pos: 3 })
+        );
+        assert_eq!(
+            parse_regex("^N(E|W))$"),
+            Err(RegexError::UnmatchedClose { pos: 7 })
+        );
+        assert_eq!(
+            parse_regex("^NEX$"),
+            Err(RegexError::UnexpectedChar { pos: 3, found: 'X' })
+        );
+        assert_eq!(
+            parse_regex("^N|S$"),
+            Err(RegexError::AlternativeOutsideGroup { pos: 2 })
+        );
+    }
+
+    #[test]
+    fn test_render_example() {
+        assert_eq!(
+            parse("^ENWWW(NEEE|SSE(EE|N))$").render(),
+            "\
+#########
+#.|.|.|.#
+#-#######
+#.|.|.|.#
+#-#####-#
+#.#.#X|.#
+#-#-#####
+#.|.|.|.#
+#########
+"
+        );
+        assert_eq!(
+            parse(EXAMPLE_DATA).render(),
+            "\
+###############
+#.|.|.|.#.|.|.#
+#-###-###-#-#-#
+#.|.#.|.|.#.#.#
+#-#########-#-#
+#.#.|.|.|.|.#.#
+#-#-#########-#
+#.#.#.|X#.|.#.#
+###-#-###-#-#-#
+#.|.#.#.|.#.|.#
+#-###-#####-###
+#.|.#.|.|.#.#.#
+#-#-#####-#-#-#
+#.#.|.|.|.#.|.#
+###############
+"
+        );
+    }
+
+    #[test]
+    fn test_shortest_path() {
+        let map = parse("^WNE$");
+        assert_eq!(map.farthest_room(), ((-1, 0), 3));
+        assert_eq!(
+            map.shortest_path(ORIGIN, (-1, 0)),
+            Some(vec![(0, 0), (0, -1), (-1, -1), (-1, 0)])
+        );
+        assert_eq!(map.shortest_path(ORIGIN, (5, 5)), None);
+    }
+
     #[test]
     fn test_p1_real() {
         assert_eq!(calculate_p1(&parse(&REAL_DATA)), 3839);
@@ -1,14 +1,17 @@
-use advent_of_code_2018::vm::{Instruction, apply_instruction, parse};
+use advent_of_code_2018::cycle::find_cycle;
+use advent_of_code_2018::vm::{Instruction, RegisterState, apply_instruction, parse};
 use advent_of_code_2018::{Cli, Parser};
 use std::fs;
 
-fn calculate(ip_register: usize, data: &[Instruction]) -> (usize, usize) {
-    let mut ip = 0;
-    let mut register_state = [0; 6];
-
-    let mut possible_answers = vec![];
+const CHECK_IP: usize = 28;
 
-    let result_register = data[28].a;
+/// Runs the program from the given (ip, registers) state until the next time
+/// it reaches the halting check, or `None` if it halts first.
+fn run_to_check(
+    ip_register: usize,
+    data: &[Instruction],
+    (mut ip, mut register_state): (usize, RegisterState<6>),
+) -> Option<(usize, RegisterState<6>)> {
     let div_result_register = data[26].out;
 
     while ip < data.len() {
@@ -18,20 +21,38 @@ fn calculate(ip_register: usize, data: &[Instruction]) -> (usize, usize) {
             ip = 27;
             continue;
         }
-        if ip == 28 {
-            let r = register_state[result_register];
-            if possible_answers.contains(&r) {
-                break;
-            }
-            possible_answers.push(r);
-        }
         register_state[ip_register] = ip;
         apply_instruction(&mut register_state, &data[ip]);
         ip = register_state[ip_register] + 1;
+
+        if ip == CHECK_IP {
+            return Some((ip, register_state));
+        }
     }
+    None
+}
 
-    let p1 = *possible_answers.first().expect("nonempty");
-    let p2 = *possible_answers.last().expect("nonempty");
+fn calculate(ip_register: usize, data: &[Instruction]) -> (usize, usize) {
+    let result_register = data[CHECK_IP].a;
+
+    let checked = |state: &Option<(usize, RegisterState<6>)>| {
+        state.map(|(_, register_state)| register_state[result_register])
+    };
+
+    // Halting ends the sequence with a `None`, which then repeats forever.
+    let first_check = run_to_check(ip_register, data, (0, [0; 6]));
+    let (cycle, history) = find_cycle(
+        first_check,
+        |&state| state.and_then(|state| run_to_check(ip_register, data, state)),
+        checked,
+    );
+
+    let p1 = checked(&history[0]).expect("program halts before the check");
+    let p2 = history[..cycle.start + cycle.length]
+        .iter()
+        .rev()
+        .find_map(checked)
+        .expect("nonempty");
     (p1, p2)
 }
 
@@ -48,9 +69,28 @@ fn main() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use itertools::Itertools;
 
     const REAL_DATA: &str = include_str!("../../inputs/real/2018_21");
 
+    #[test]
+    fn test_halting_program() {
+        // Jumps to the check, then counts register 2 up until it passes 2.
+        let program = ["#ip 5", "seti 27 0 5"]
+            .into_iter()
+            .chain(["seti 0 0 0"; 26])
+            .chain([
+                "seti 0 0 0",
+                "addi 2 1 2",
+                "gtri 2 2 3",
+                "addr 3 5 5",
+                "seti 27 0 5",
+            ])
+            .join("\n");
+        let (ipr, data) = parse(&program);
+        assert_eq!(calculate(ipr, &data), (0, 2));
+    }
+
     #[test]
     fn test_real() {
         let (ipr, data) = parse(REAL_DATA);
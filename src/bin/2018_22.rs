@@ -1,15 +1,64 @@
+use advent_of_code_2018::bitvec_set::BitVecSet3D;
 use advent_of_code_2018::{Cli, Parser};
+use ahash::AHashMap;
 use itertools::Itertools;
-use ndarray::{Array2, Array3};
-use std::{collections::BinaryHeap, fs};
+use std::cmp::Reverse;
+use std::{collections::BinaryHeap, fmt, fs};
 
-struct Data {
+const TORCH: usize = 0;
+const CLIMB: usize = 1;
+const NEITHER: usize = 2;
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+struct CaveRules {
     depth: usize,
     tx: usize,
     ty: usize,
+    x_multiplier: usize,
+    y_multiplier: usize,
+    modulo: usize,
+    switch_cost: usize,
+    // Indexed as [region][tool].
+    allowed_tools: [[bool; 3]; 3],
+}
+
+impl CaveRules {
+    fn new(depth: usize, tx: usize, ty: usize) -> CaveRules {
+        CaveRules {
+            depth,
+            tx,
+            ty,
+            x_multiplier: 16807,
+            y_multiplier: 48271,
+            modulo: 20183,
+            switch_cost: 7,
+            // Rocky, wet and narrow regions in turn.
+            allowed_tools: [[CLIMB, TORCH], [CLIMB, NEITHER], [TORCH, NEITHER]]
+                .map(|tools| std::array::from_fn(|tool| tools.contains(&tool))),
+        }
+    }
+
+    fn is_allowed(&self, region: usize, tool: usize) -> bool {
+        self.allowed_tools[region][tool]
+    }
+
+    fn regions_connect(&self) -> bool {
+        (0..3)
+            .cartesian_product(0..3)
+            .all(|(a, b)| (0..3).any(|tool| self.is_allowed(a, tool) && self.is_allowed(b, tool)))
+    }
+}
+
+#[derive(PartialEq, Eq, Debug)]
+struct UnsupportedRules;
+
+impl fmt::Display for UnsupportedRules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "some pair of region types shares no tool")
+    }
 }
 
-fn parse(raw_inp: &str) -> Data {
+fn parse(raw_inp: &str) -> CaveRules {
     let (depth_line, target_line) = raw_inp.trim().split_once("\n").expect("invalid format");
 
     let depth = depth_line
@@ -28,126 +77,321 @@ fn parse(raw_inp: &str) -> Data {
         .collect_tuple()
         .expect("invalid format");
 
-    Data { depth, tx, ty }
-}
-
-fn geologic_index(x: usize, y: usize, geologic_indices: &mut Array2<usize>, data: &Data) -> usize {
-    let mut g = if (x == 0 && y == 0) || (x == data.tx && y == data.ty) {
-        0
-    } else if y == 0 {
-        x * 16807
-    } else if x == 0 {
-        y * 48271
-    } else {
-        let up = geologic_indices
-            .get((y - 1, x))
-            .copied()
-            .unwrap_or_else(|| geologic_index(x, y - 1, geologic_indices, data));
-        let left = geologic_indices
-            .get((y, x - 1))
-            .copied()
-            .unwrap_or_else(|| geologic_index(x - 1, y, geologic_indices, data));
-        up * left
-    };
-    g += data.depth;
-    g %= 20183;
-    geologic_indices[(y, x)] = g;
-    g
+    CaveRules::new(depth, tx, ty)
 }
 
-fn erosion_level(x: usize, y: usize, geologic_indices: &mut Array2<usize>, data: &Data) -> usize {
-    geologic_index(x, y, geologic_indices, data) % 3
+// Erosion levels are computed on demand, so a search can go arbitrarily far.
+struct Cave<'a> {
+    rules: &'a CaveRules,
+    // Row lengths never increase going down.
+    erosion_levels: Vec<Vec<usize>>,
 }
 
-const TORCH: usize = 0;
-const CLIMB: usize = 1;
-const NEITHER: usize = 2;
+impl<'a> Cave<'a> {
+    fn new(rules: &'a CaveRules) -> Cave<'a> {
+        Cave {
+            rules,
+            erosion_levels: vec![],
+        }
+    }
 
-#[inline]
-fn valid_gear(erosion_level: usize) -> [usize; 2] {
-    match erosion_level {
-        0 => [CLIMB, TORCH],
-        1 => [CLIMB, NEITHER],
-        2 => [TORCH, NEITHER],
-        _ => panic!("bad erosion level"),
+    fn geologic_index(&self, x: usize, y: usize) -> usize {
+        if (x == 0 && y == 0) || (x == self.rules.tx && y == self.rules.ty) {
+            0
+        } else if y == 0 {
+            x * self.rules.x_multiplier
+        } else if x == 0 {
+            y * self.rules.y_multiplier
+        } else {
+            self.erosion_levels[y - 1][x] * self.erosion_levels[y][x - 1]
+        }
     }
-}
 
-#[inline]
-fn change_gear(erosion_level: usize, current_gear: usize) -> usize {
-    valid_gear(erosion_level)
-        .into_iter()
-        .find(|&g| g != current_gear)
-        .expect("no valid gear to change to")
+    fn erosion_level(&mut self, x: usize, y: usize) -> usize {
+        if let Some(&e) = self.erosion_levels.get(y).and_then(|row| row.get(x)) {
+            return e;
+        }
+
+        if self.erosion_levels.len() <= y {
+            self.erosion_levels.resize_with(y + 1, Vec::new);
+        }
+        for row in 0..=y {
+            for col in self.erosion_levels[row].len()..=x {
+                let e = (self.geologic_index(col, row) + self.rules.depth) % self.rules.modulo;
+                self.erosion_levels[row].push(e);
+            }
+        }
+        self.erosion_levels[y][x]
+    }
+
+    // 0 for rocky, 1 for wet and 2 for narrow.
+    fn region_type(&mut self, x: usize, y: usize) -> usize {
+        self.erosion_level(x, y) % 3
+    }
 }
 
 const DIRS: [(isize, isize); 4] = [(0, 1), (0, -1), (1, 0), (-1, 0)];
-const PADDING: usize = 20;
-
-fn calculate(data: &Data) -> (usize, i64) {
-    let mut p1 = 0;
-    let mut p2: i64 = -1;
-    let mut geologic_indices = Array2::from_elem((data.ty + PADDING, data.tx + PADDING), 0);
-    let mut costs = Array3::from_elem((data.ty + PADDING, data.tx + PADDING, 3), i64::MIN);
-
-    for y in 0..data.ty + PADDING {
-        for x in 0..data.tx + PADDING {
-            let e = erosion_level(x, y, &mut geologic_indices, data);
-            if y <= data.ty && x <= data.tx {
-                p1 += e;
+
+// Grows the set if the search has gone beyond its bounds.
+fn visit(visited: &mut BitVecSet3D, state: [usize; 3]) -> bool {
+    if visited.get(&state).is_none() {
+        let bounds = std::array::from_fn(|i| {
+            let bound = visited.bounds[i];
+            if state[i] < bound {
+                bound
+            } else {
+                2 * (state[i] + 1)
             }
+        });
+        visited.resize(bounds);
+    }
+    visited.insert(state)
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum Action {
+    Move { x: usize, y: usize },
+    Switch { gear: usize },
+}
+
+impl Action {
+    fn minutes(&self, rules: &CaveRules) -> usize {
+        match self {
+            Action::Move { .. } => 1,
+            Action::Switch { .. } => rules.switch_cost,
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Move { x, y } => write!(f, "move to {x},{y}"),
+            Action::Switch { gear } => write!(f, "switch to {}", GEAR_NAMES[*gear]),
         }
     }
+}
+
+const GEAR_NAMES: [&str; 3] = ["the torch", "climbing gear", "neither"];
 
-    let mut b_heap = BinaryHeap::default();
-    b_heap.push((0, (0, 0, TORCH)));
+struct Route {
+    minutes: usize,
+    actions: Vec<Action>,
+}
 
-    while let Some((cost, (x, y, gear))) = b_heap.pop() {
-        if cost <= costs[(y, x, gear)] {
+impl Route {
+    fn states(&self) -> impl Iterator<Item = (usize, usize, usize)> + use<'_> {
+        self.actions
+            .iter()
+            .scan((0, 0, TORCH), |(x, y, gear), action| {
+                match *action {
+                    Action::Move { x: nx, y: ny } => (*x, *y) = (nx, ny),
+                    Action::Switch { gear: g } => *gear = g,
+                }
+                Some((*x, *y, *gear))
+            })
+    }
+}
+
+// The estimate is the Manhattan distance plus a switch if the torch isn't held.
+// Without a tool shared by every pair of regions the search might never end.
+fn fastest_route(cave: &mut Cave) -> Result<Option<Route>, UnsupportedRules> {
+    let rules = cave.rules;
+    let (tx, ty) = (rules.tx, rules.ty);
+    if !rules.is_allowed(cave.region_type(0, 0), TORCH)
+        || !rules.is_allowed(cave.region_type(tx, ty), TORCH)
+    {
+        return Ok(None);
+    }
+    if !rules.regions_connect() {
+        return Err(UnsupportedRules);
+    }
+
+    let estimate = |[y, x, gear]: [usize; 3]| {
+        x.abs_diff(tx) + y.abs_diff(ty) + if gear == TORCH { 0 } else { rules.switch_cost }
+    };
+    let start = [0, 0, TORCH];
+    let target = [ty, tx, TORCH];
+
+    let mut visited = BitVecSet3D::new([ty + 1, tx + 1, 3]);
+    let mut came_from = AHashMap::default();
+    let mut heap = BinaryHeap::from([Reverse((estimate(start), 0, start, start))]);
+
+    while let Some(Reverse((_, minutes, state, prev))) = heap.pop() {
+        if !visit(&mut visited, state) {
             continue;
         }
-        costs[(y, x, gear)] = cost;
-        if x == data.tx && y == data.ty && gear == TORCH {
-            p2 = -cost;
-            break;
+        came_from.insert(state, prev);
+        if state == target {
+            return Ok(Some(Route {
+                minutes,
+                actions: reconstruct(&came_from, start, target),
+            }));
         }
-        let e = erosion_level(x, y, &mut geologic_indices, data);
-        let cg = change_gear(e, gear);
-        if cost - 7 > costs[(y, x, cg)] {
-            b_heap.push((cost - 7, (x, y, cg)));
+
+        let [y, x, gear] = state;
+        let region = cave.region_type(x, y);
+        for tool in (0..3).filter(|&t| t != gear && rules.is_allowed(region, t)) {
+            let switched = [y, x, tool];
+            if !visited.contains(&switched) {
+                let minutes = minutes + rules.switch_cost;
+                heap.push(Reverse((
+                    minutes + estimate(switched),
+                    minutes,
+                    switched,
+                    state,
+                )));
+            }
         }
 
         for dir in DIRS {
             if let Some(nx) = x.checked_add_signed(dir.0)
                 && let Some(ny) = y.checked_add_signed(dir.1)
             {
-                if ny >= data.ty + PADDING || nx >= data.tx + PADDING {
-                    continue;
-                }
-                let ne = erosion_level(nx, ny, &mut geologic_indices, data);
-                if valid_gear(ne).contains(&gear) && cost - 1 > costs[(ny, nx, gear)] {
-                    b_heap.push((cost - 1, (nx, ny, gear)));
+                let next = [ny, nx, gear];
+                if rules.is_allowed(cave.region_type(nx, ny), gear) && !visited.contains(&next) {
+                    heap.push(Reverse((
+                        minutes + 1 + estimate(next),
+                        minutes + 1,
+                        next,
+                        state,
+                    )));
                 }
             }
         }
     }
 
+    unreachable!("the cave is unbounded, so the search always reaches the target")
+}
+
+fn reconstruct(
+    came_from: &AHashMap<[usize; 3], [usize; 3]>,
+    start: [usize; 3],
+    target: [usize; 3],
+) -> Vec<Action> {
+    let mut actions = vec![];
+    let mut state = target;
+    while state != start {
+        let prev = came_from[&state];
+        let [y, x, gear] = state;
+        actions.push(if prev[2] == gear {
+            Action::Move { x, y }
+        } else {
+            Action::Switch { gear }
+        });
+        state = prev;
+    }
+    actions.reverse();
+    actions
+}
+
+// Squares on the route show the gear they're left with.
+fn render(cave: &mut Cave, route: &Route) -> String {
+    let (tx, ty) = (cave.rules.tx, cave.rules.ty);
+    let gear_at = route
+        .states()
+        .map(|(x, y, gear)| ((x, y), gear))
+        .collect::<AHashMap<_, _>>();
+    let (width, height) = gear_at
+        .keys()
+        .fold((tx, ty), |(w, h), &(x, y)| (w.max(x), h.max(y)));
+
+    (0..=height + 1)
+        .map(|y| {
+            (0..=width + 1)
+                .map(|x| match gear_at.get(&(x, y)) {
+                    _ if (x, y) == (0, 0) => 'M',
+                    _ if (x, y) == (tx, ty) => 'T',
+                    Some(&gear) => [b't', b'c', b'n'][gear] as char,
+                    None => ['.', '=', '|'][cave.region_type(x, y)],
+                })
+                .collect::<String>()
+                + "\n"
+        })
+        .collect()
+}
+
+fn calculate(rules: &CaveRules) -> (usize, i64) {
+    let mut cave = Cave::new(rules);
+    let p1 = (0..=rules.ty)
+        .cartesian_product(0..=rules.tx)
+        .map(|(y, x)| cave.region_type(x, y))
+        .sum();
+
+    let p2 = fastest_route(&mut cave)
+        .unwrap_or_else(|e| panic!("{e}"))
+        .map_or(-1, |route| route.minutes as i64);
+
     (p1, p2)
 }
 
+#[derive(Parser)]
+struct Day22Cli {
+    #[clap(flatten)]
+    cli: Cli,
+
+    /// Draw a fastest route to the target over the cave, followed by its
+    /// steps, instead of printing the answers.
+    #[clap(long)]
+    route: bool,
+
+    /// Minutes taken to change tools.
+    #[clap(long, default_value_t = 7)]
+    switch_cost: usize,
+
+    /// Geologic index per square along the top row.
+    #[clap(long, default_value_t = 16807)]
+    x_multiplier: usize,
+
+    /// Geologic index per square down the left column.
+    #[clap(long, default_value_t = 48271)]
+    y_multiplier: usize,
+
+    /// Modulus for erosion levels.
+    #[clap(long, default_value_t = 20183)]
+    modulo: usize,
+}
+
 fn main() {
-    let args = Cli::parse();
+    let args = Day22Cli::parse();
 
-    let inp = fs::read_to_string(args.input).expect("can't open input file");
+    let inp = fs::read_to_string(&args.cli.input).expect("can't open input file");
+
+    let rules = CaveRules {
+        switch_cost: args.switch_cost,
+        x_multiplier: args.x_multiplier,
+        y_multiplier: args.y_multiplier,
+        modulo: args.modulo,
+        ..parse(&inp)
+    };
+
+    if args.route {
+        let mut cave = Cave::new(&rules);
+        let route = match fastest_route(&mut cave) {
+            Ok(Some(route)) => route,
+            Ok(None) => {
+                println!("no route to the target with these rules");
+                return;
+            }
+            Err(e) => panic!("{e}"),
+        };
+        print!("{}", render(&mut cave, &route));
+        let mut minutes = 0;
+        for action in &route.actions {
+            minutes += action.minutes(&rules);
+            println!("{minutes}: {action}");
+        }
+        return;
+    }
 
-    let data = parse(&inp);
-    let (p1, p2) = calculate(&data);
+    let (p1, p2) = calculate(&rules);
     println!("{p1}\n{p2}");
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ndarray::{Array2, Array3};
 
     const EXAMPLE_DATA: &str = include_str!("../../inputs/examples/2018_22");
     const REAL_DATA: &str = include_str!("../../inputs/real/2018_22");
@@ -157,6 +401,186 @@ mod tests {
         assert_eq!(calculate(&parse(EXAMPLE_DATA)), (114, 45));
     }
 
+    // The original search, over a fixed grid with its own erosion levels.
+    fn bounded_minutes(rules: &CaveRules, padding: usize) -> Option<usize> {
+        let (height, width) = (rules.ty + padding, rules.tx + padding);
+        let mut erosion = Array2::zeros((height, width));
+        for y in 0..height {
+            for x in 0..width {
+                let index = if (x, y) == (0, 0) || (x, y) == (rules.tx, rules.ty) {
+                    0
+                } else if y == 0 {
+                    x * rules.x_multiplier
+                } else if x == 0 {
+                    y * rules.y_multiplier
+                } else {
+                    erosion[(y - 1, x)] * erosion[(y, x - 1)]
+                };
+                erosion[(y, x)] = (index + rules.depth) % rules.modulo;
+            }
+        }
+        let allowed = |y: usize, x: usize, gear| rules.is_allowed(erosion[(y, x)] % 3, gear);
+
+        let mut done = Array3::from_elem((height, width, 3), false);
+        let mut heap = BinaryHeap::from([Reverse((0, 0, 0, TORCH))]);
+        while let Some(Reverse((minutes, y, x, gear))) = heap.pop() {
+            if std::mem::replace(&mut done[(y, x, gear)], true) {
+                continue;
+            }
+            if (x, y, gear) == (rules.tx, rules.ty, TORCH) {
+                return Some(minutes);
+            }
+            for tool in (0..3).filter(|&t| t != gear && allowed(y, x, t)) {
+                heap.push(Reverse((minutes + rules.switch_cost, y, x, tool)));
+            }
+            for (ny, nx) in [
+                (y.wrapping_sub(1), x),
+                (y + 1, x),
+                (y, x.wrapping_sub(1)),
+                (y, x + 1),
+            ] {
+                if ny < height && nx < width && allowed(ny, nx, gear) {
+                    heap.push(Reverse((minutes + 1, ny, nx, gear)));
+                }
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_route_beyond_target() {
+        // The fastest route strays more than 20 squares below the target.
+        let rules = parse("depth: 5670\ntarget: 280,0\n");
+        assert_eq!(bounded_minutes(&rules, 20), Some(410));
+        assert_eq!(bounded_minutes(&rules, 200), Some(405));
+        assert_eq!(calculate(&rules), (281, 405));
+    }
+
+    #[test]
+    fn test_matches_bounded_search() {
+        let mut rng = fastrand::Rng::with_seed(45);
+        for _ in 0..30 {
+            let rules = CaveRules::new(rng.usize(..20000), rng.usize(..40), rng.usize(..40));
+            let minutes = fastest_route(&mut Cave::new(&rules))
+                .unwrap()
+                .map(|r| r.minutes);
+            if minutes.is_some() {
+                assert_eq!(minutes, bounded_minutes(&rules, 100), "{rules:?}");
+            }
+        }
+    }
+
+    fn check_route(data: &CaveRules, expected_minutes: usize) {
+        let mut cave = Cave::new(data);
+        let route = fastest_route(&mut cave).unwrap().expect("reachable target");
+        assert_eq!(route.minutes, expected_minutes);
+        assert_eq!(
+            route.actions.iter().map(|a| a.minutes(data)).sum::<usize>(),
+            route.minutes
+        );
+
+        let mut prev = (0, 0, TORCH);
+        for (x, y, gear) in route.states() {
+            assert!(data.is_allowed(cave.region_type(x, y), gear));
+            assert_eq!(
+                x.abs_diff(prev.0) + y.abs_diff(prev.1) + usize::from(gear != prev.2),
+                1
+            );
+            prev = (x, y, gear);
+        }
+        assert_eq!(prev, (data.tx, data.ty, TORCH));
+    }
+
+    #[test]
+    fn test_routes() {
+        check_route(&parse(EXAMPLE_DATA), 45);
+        check_route(&parse("depth: 5670\ntarget: 280,0\n"), 405);
+    }
+
+    #[test]
+    fn test_render_example() {
+        let data = parse(EXAMPLE_DATA);
+        let mut cave = Cave::new(&data);
+        let route = fastest_route(&mut cave).unwrap().expect("reachable target");
+        let rendered = render(&mut cave, &route);
+        let lines = rendered.lines().collect_vec();
+
+        assert!(lines[0].starts_with("M=.|=.|.|=.|"));
+        assert_eq!(lines[10].as_bytes()[10], b'T');
+        assert_eq!(
+            rendered.bytes().filter(|c| b"tcn".contains(c)).count(),
+            route.states().map(|(x, y, _)| (x, y)).unique().count() - 1
+        );
+    }
+
+    #[test]
+    fn test_torch_unusable_at_target() {
+        // With a depth of 1 the target's erosion level is 1, which is wet.
+        let data = parse("depth: 1\ntarget: 3,3\n");
+        assert!(matches!(fastest_route(&mut Cave::new(&data)), Ok(None)));
+        assert_eq!(calculate(&data).1, -1);
+    }
+
+    #[test]
+    fn test_cost_monotonic_in_switch_cost() {
+        let mut rng = fastrand::Rng::with_seed(22);
+        for _ in 0..30 {
+            let puzzle = CaveRules::new(rng.usize(..20000), rng.usize(..25), rng.usize(..25));
+            let minutes = (0..=12)
+                .map(|switch_cost| {
+                    let rules = CaveRules {
+                        switch_cost,
+                        ..puzzle.clone()
+                    };
+                    fastest_route(&mut Cave::new(&rules))
+                        .unwrap()
+                        .map(|r| r.minutes)
+                })
+                .collect_vec();
+            if minutes[0].is_none() {
+                assert!(minutes.iter().all(Option::is_none));
+                continue;
+            }
+            let minutes = minutes.into_iter().map(Option::unwrap).collect_vec();
+            assert!(minutes.is_sorted(), "{puzzle:?}: {minutes:?}");
+            assert!(minutes[0] >= puzzle.tx + puzzle.ty);
+        }
+    }
+
+    #[test]
+    fn test_variant_rules() {
+        // With every tool allowed everywhere, the route is a straight walk.
+        let rules = CaveRules {
+            allowed_tools: [[true; 3]; 3],
+            ..CaveRules::new(510, 10, 10)
+        };
+        assert_eq!(
+            fastest_route(&mut Cave::new(&rules))
+                .unwrap()
+                .unwrap()
+                .minutes,
+            20
+        );
+
+        // A different modulus changes the layout, and so the risk level.
+        let rules = CaveRules {
+            modulo: 20143,
+            ..CaveRules::new(510, 10, 10)
+        };
+        assert_ne!(calculate(&rules).0, 114);
+        check_route(&rules, calculate(&rules).1 as usize);
+    }
+
+    #[test]
+    fn test_disconnected_rules() {
+        let mut rules = CaveRules::new(510, 10, 10);
+        rules.allowed_tools[1] = [false, false, true];
+        assert!(matches!(
+            fastest_route(&mut Cave::new(&rules)),
+            Err(UnsupportedRules)
+        ));
+    }
+
     #[test]
     fn test_real() {
         assert_eq!(calculate(&parse(REAL_DATA)), (7915, 980));
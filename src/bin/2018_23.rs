@@ -1,8 +1,13 @@
-use advent_of_code_2018::{Cli, Parser};
+use advent_of_code_2018::{
+    Cli, Parser,
+    geometry::{Cuboid, Octahedron, Point, RotatedBox},
+};
 use itertools::Itertools;
-use std::{collections::BinaryHeap, fs, str::FromStr};
+use ndarray::Array2;
+use std::cmp::Reverse;
+use std::{collections::BinaryHeap, fmt, fs, str::FromStr};
 
-#[derive(PartialEq, Eq)]
+#[derive(PartialEq, Eq, Debug)]
 struct Nanobot {
     x: i64,
     y: i64,
@@ -11,13 +16,19 @@ struct Nanobot {
 }
 
 impl Nanobot {
-    fn in_range_coord(&self, (x, y, z): (i64, i64, i64)) -> bool {
-        let diff = self.x.abs_diff(x) + self.y.abs_diff(y) + self.z.abs_diff(z);
-        diff <= self.r
+    fn pos(&self) -> Point {
+        [self.x, self.y, self.z]
+    }
+
+    fn range(&self) -> Octahedron {
+        Octahedron {
+            center: self.pos(),
+            radius: self.r,
+        }
     }
 
     fn in_range(&self, other: &Nanobot) -> bool {
-        self.in_range_coord((other.x, other.y, other.z))
+        self.range().contains(other.pos())
     }
 }
 
@@ -38,128 +49,101 @@ impl FromStr for Nanobot {
     }
 }
 
-#[derive(PartialEq, Eq, Clone, Copy, Debug)]
-struct Voxel {
-    start_x: i64,
-    start_y: i64,
-    start_z: i64,
-    end_x: i64,
-    end_y: i64,
-    end_z: i64,
+impl fmt::Display for Nanobot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "pos=<{},{},{}>, r={}", self.x, self.y, self.z, self.r)
+    }
 }
 
-impl PartialOrd for Voxel {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
-    }
+fn bounds(bots: &[Nanobot]) -> Cuboid {
+    bots.iter()
+        .map(|b| b.range().bounding_box())
+        .reduce(|a, b| a.union(&b))
+        .expect("nonempty")
 }
 
-impl Ord for Voxel {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        other.manhattan().cmp(&self.manhattan())
-    }
+struct Swarm {
+    bots: Vec<Nanobot>,
 }
 
-impl Voxel {
-    fn manhattan(&self) -> i64 {
-        self.start_x.abs() + self.start_y.abs() + self.start_z.abs()
+impl Swarm {
+    fn covering(&self, p: Point) -> Vec<usize> {
+        self.bots
+            .iter()
+            .positions(|b| b.range().contains(p))
+            .collect()
     }
 
-    fn count_maybe_in_range(&self, bots: &[Nanobot]) -> usize {
-        bots.iter().filter(|b| self.maybe_in_range(b)).count()
+    fn coverage(&self, p: Point) -> usize {
+        self.bots.iter().filter(|b| b.range().contains(p)).count()
     }
 
-    fn maybe_in_range(&self, bot: &Nanobot) -> bool {
-        let xd = bot.x.abs_diff(self.start_x);
-        let yd = bot.y.abs_diff(self.start_y);
-        let zd = bot.z.abs_diff(self.start_z);
-
-        let xv = self.start_x.abs_diff(self.end_x);
-        let yv = self.start_y.abs_diff(self.end_y);
-        let zv = self.start_z.abs_diff(self.end_z);
-
-        xd + yd + zd <= bot.r + xv + yv + zv
+    fn heatmap(
+        &self,
+        z: i64,
+        (x, y): (i64, i64),
+        step: i64,
+        shape: (usize, usize),
+    ) -> Array2<usize> {
+        Array2::from_shape_fn(shape, |(row, col)| {
+            self.coverage([x + col as i64 * step, y + row as i64 * step, z])
+        })
     }
 
-    fn volume(&self) -> u64 {
-        (self.end_x.abs_diff(self.start_x) + 1)
-            .saturating_mul(self.end_y.abs_diff(self.start_y) + 1)
-            .saturating_mul(self.end_z.abs_diff(self.start_z) + 1)
-    }
+    // Stop at a box once the bots touching it share a region: only points
+    // there can be in range of all of them.
+    fn max_overlap(&self) -> (usize, Vec<RotatedBox>) {
+        let entry = |v: Cuboid| {
+            let touching = self.bots.iter().filter(|b| b.range().intersects_box(&v));
+            (touching.count(), v)
+        };
+        let mut q = BinaryHeap::from([entry(bounds(&self.bots))]);
+        let mut best = None;
+        let mut regions = vec![];
+
+        while let Some((count, cuboid)) = q.pop() {
+            if best.is_some_and(|best| count < best) {
+                break;
+            }
+            let shared = self
+                .bots
+                .iter()
+                .map(|b| b.range())
+                .filter(|r| r.intersects_box(&cuboid))
+                .map(|r| r.rotated())
+                .reduce(|a, b| a.intersection(&b))
+                .filter(|shared| !shared.is_empty());
+            match shared {
+                Some(shared) => {
+                    best = Some(count);
+                    if !regions.contains(&shared) {
+                        regions.push(shared);
+                    }
+                }
+                None => q.extend(cuboid.split().map(entry)),
+            }
+        }
 
-    fn voxelate(&self) -> [Voxel; 8] {
-        let xm = (self.start_x + self.end_x) / 2;
-        let ym = (self.start_y + self.end_y) / 2;
-        let zm = (self.start_z + self.end_z) / 2;
-        [
-            Voxel {
-                start_x: self.start_x,
-                end_x: xm,
-                start_y: self.start_y,
-                end_y: ym,
-                start_z: self.start_z,
-                end_z: zm,
-            },
-            Voxel {
-                start_x: self.start_x,
-                end_x: xm,
-                start_y: self.start_y,
-                end_y: ym,
-                start_z: zm + 1,
-                end_z: self.end_z,
-            },
-            Voxel {
-                start_x: self.start_x,
-                end_x: xm,
-                start_y: ym + 1,
-                end_y: self.end_y,
-                start_z: self.start_z,
-                end_z: zm,
-            },
-            Voxel {
-                start_x: self.start_x,
-                end_x: xm,
-                start_y: ym + 1,
-                end_y: self.end_y,
-                start_z: zm + 1,
-                end_z: self.end_z,
-            },
-            Voxel {
-                start_x: xm + 1,
-                end_x: self.end_x,
-                start_y: self.start_y,
-                end_y: ym,
-                start_z: self.start_z,
-                end_z: zm,
-            },
-            Voxel {
-                start_x: xm + 1,
-                end_x: self.end_x,
-                start_y: self.start_y,
-                end_y: ym,
-                start_z: zm + 1,
-                end_z: self.end_z,
-            },
-            Voxel {
-                start_x: xm + 1,
-                end_x: self.end_x,
-                start_y: ym + 1,
-                end_y: self.end_y,
-                start_z: self.start_z,
-                end_z: zm,
-            },
-            Voxel {
-                start_x: xm + 1,
-                end_x: self.end_x,
-                start_y: ym + 1,
-                end_y: self.end_y,
-                start_z: zm + 1,
-                end_z: self.end_z,
-            },
-        ]
+        (best.expect("nonempty"), regions)
     }
 }
 
+const SHADES: &[u8] = b" .:-=+*#%@";
+
+fn render_heatmap(counts: &Array2<usize>) -> String {
+    let most = counts.iter().copied().max().unwrap_or(0).max(1);
+    counts
+        .rows()
+        .into_iter()
+        .map(|row| {
+            row.iter()
+                .map(|&c| SHADES[(c * (SHADES.len() - 1)).div_ceil(most)] as char)
+                .collect::<String>()
+                + "\n"
+        })
+        .collect()
+}
+
 fn parse(raw_inp: &str) -> Vec<Nanobot> {
     raw_inp
         .trim()
@@ -176,64 +160,113 @@ fn calculate_p1(data: &[Nanobot]) -> usize {
         .count()
 }
 
+// Both bounds are exact for a single point, so the first point popped is best.
 fn calculate_p2(data: &[Nanobot]) -> i64 {
-    let (min_x, max_x) = data
-        .iter()
-        .map(|b| b.x)
-        .minmax()
-        .into_option()
-        .expect("nonempty");
-    let (min_y, max_y) = data
-        .iter()
-        .map(|b| b.y)
-        .minmax()
-        .into_option()
-        .expect("nonempty");
-    let (min_z, max_z) = data
-        .iter()
-        .map(|b| b.z)
-        .minmax()
-        .into_option()
-        .expect("nonempty");
-
-    let mut q = BinaryHeap::default();
-    q.push((
-        data.len(),
-        Voxel {
-            start_x: min_x,
-            start_y: min_y,
-            start_z: min_z,
-            end_x: max_x,
-            end_y: max_y,
-            end_z: max_z,
-        },
-    ));
-
-    while let Some((_, voxel)) = q.pop() {
-        match voxel.volume() {
-            1 => {
-                return voxel.manhattan();
-            }
-            _ => {
-                voxel
-                    .voxelate()
-                    .iter()
-                    .for_each(|v| q.push((v.count_maybe_in_range(data), *v)));
-            }
+    let entry = |v: Cuboid| {
+        (
+            data.iter().filter(|b| b.range().intersects_box(&v)).count(),
+            Reverse(v.distance_to([0, 0, 0])),
+            Reverse(v.volume()),
+            v,
+        )
+    };
+    let mut q = BinaryHeap::from([entry(bounds(data))]);
+
+    while let Some((_, Reverse(distance), _, cuboid)) = q.pop() {
+        if cuboid.is_point() {
+            return distance as i64;
         }
+        q.extend(cuboid.split().map(entry));
     }
 
     panic!("no p2 answer")
 }
 
+fn parse_point(s: &str) -> Result<Point, String> {
+    let (x, y, z) = s
+        .split(',')
+        .map(|v| v.trim().parse::<i64>().map_err(|e| e.to_string()))
+        .collect_tuple()
+        .ok_or_else(|| format!("expected x,y,z but got {s}"))?;
+    Ok([x?, y?, z?])
+}
+
+#[derive(Parser)]
+struct Day23Cli {
+    #[clap(flatten)]
+    cli: Cli,
+
+    /// List the bots in range of a point given as x,y,z.
+    #[clap(long, value_parser = parse_point)]
+    covering: Option<Point>,
+
+    /// Draw how many bots are in range of each point on the slice at this
+    /// height, across the bots' ranges.
+    #[clap(long, allow_negative_numbers = true)]
+    heatmap: Option<i64>,
+
+    /// Number of samples along the longer side of the heatmap.
+    #[clap(long, default_value_t = 64)]
+    heatmap_size: usize,
+
+    /// List the regions of points in range of the most bots, as bounds on
+    /// x+y+z, x+y-z, x-y+z and -x+y+z.
+    #[clap(long)]
+    overlap: bool,
+}
+
 fn main() {
-    let args = Cli::parse();
+    let args = Day23Cli::parse();
 
-    let inp = fs::read_to_string(args.input).expect("can't open input file");
+    let inp = fs::read_to_string(&args.cli.input).expect("can't open input file");
 
-    let data = parse(&inp);
-    let p1 = calculate_p1(&data);
-    let p2 = calculate_p2(&data);
+    let swarm = Swarm { bots: parse(&inp) };
+
+    if let Some(p) = args.covering {
+        let covering = swarm.covering(p);
+        println!("{} bots in range", covering.len());
+        for i in covering {
+            println!("{i}: {}", swarm.bots[i]);
+        }
+        return;
+    }
+
+    if let Some(z) = args.heatmap {
+        let Cuboid { min, max } = bounds(&swarm.bots);
+        let span = (max[0] - min[0]).max(max[1] - min[1]) + 1;
+        let step = (span as u64).div_ceil(args.heatmap_size as u64) as i64;
+        let shape = (
+            ((max[1] - min[1]) / step + 1) as usize,
+            ((max[0] - min[0]) / step + 1) as usize,
+        );
+        let counts = swarm.heatmap(z, (min[0], min[1]), step, shape);
+        println!(
+            "z={z}, x from {} and y from {} in steps of {step}, at most {} bots",
+            min[0],
+            min[1],
+            counts.iter().max().expect("nonempty")
+        );
+        print!("{}", render_heatmap(&counts));
+        return;
+    }
+
+    if args.overlap {
+        let (count, regions) = swarm.max_overlap();
+        println!("{count} bots in range");
+        for region in regions {
+            println!(
+                "{:?} to {:?}: {} points, {} from the origin",
+                region.min,
+                region.max,
+                region.point_count(),
+                region.distance_to([0, 0, 0]).expect("nonempty")
+            );
+        }
+        return;
+    }
+
+    let p1 = calculate_p1(&swarm.bots);
+    let p2 = calculate_p2(&swarm.bots);
     println!("{p1}\n{p2}");
 }
 
@@ -260,6 +293,98 @@ mod tests {
         assert_eq!(calculate_p2(&parse(EXAMPLE_DATA_2)), 36);
     }
 
+    fn brute_force_p2(bots: &[Nanobot]) -> i64 {
+        let reach = bots
+            .iter()
+            .map(|b| b.x.abs().max(b.y.abs()).max(b.z.abs()) + b.r as i64)
+            .max()
+            .expect("nonempty");
+        let axis = -reach..=reach;
+        itertools::iproduct!(axis.clone(), axis.clone(), axis)
+            .map(|(x, y, z)| {
+                let count = bots
+                    .iter()
+                    .filter(|b| b.range().contains([x, y, z]))
+                    .count();
+                (count, Reverse(x.abs() + y.abs() + z.abs()))
+            })
+            .max()
+            .map(|(_, Reverse(distance))| distance)
+            .expect("nonempty")
+    }
+
+    #[test]
+    fn test_p2_matches_brute_force() {
+        let mut rng = fastrand::Rng::with_seed(23);
+        for _ in 0..200 {
+            let bots = (0..rng.usize(1..=8))
+                .map(|_| Nanobot {
+                    x: rng.i64(-8..=8),
+                    y: rng.i64(-8..=8),
+                    z: rng.i64(-8..=8),
+                    r: rng.u64(..6),
+                })
+                .collect_vec();
+            assert_eq!(calculate_p2(&bots), brute_force_p2(&bots));
+        }
+    }
+
+    #[test]
+    fn test_max_overlap_matches_brute_force() {
+        let mut rng = fastrand::Rng::with_seed(123);
+        for _ in 0..100 {
+            let bots = (0..rng.usize(1..=8))
+                .map(|_| Nanobot {
+                    x: rng.i64(-5..=5),
+                    y: rng.i64(-5..=5),
+                    z: rng.i64(-5..=5),
+                    r: rng.u64(..5),
+                })
+                .collect_vec();
+            let swarm = Swarm { bots };
+            let (count, regions) = swarm.max_overlap();
+
+            let points = itertools::iproduct!(-9..=9, -9..=9, -9..=9)
+                .map(|(x, y, z)| [x, y, z])
+                .collect_vec();
+            let most = points.iter().map(|&p| swarm.coverage(p)).max();
+            assert_eq!(Some(count), most);
+            for p in points {
+                let holding = regions.iter().filter(|r| r.contains(p)).count();
+                assert_eq!(holding, usize::from(swarm.coverage(p) == count));
+            }
+
+            let closest = regions
+                .iter()
+                .filter_map(|r| r.distance_to([0, 0, 0]))
+                .min();
+            assert_eq!(closest, Some(calculate_p2(&swarm.bots) as u64));
+        }
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let swarm = Swarm {
+            bots: parse(EXAMPLE_DATA_2),
+        };
+        assert_eq!(swarm.covering([12, 12, 12]), vec![0, 1, 2, 3, 4]);
+
+        let counts = swarm.heatmap(12, (10, 10), 1, (5, 5));
+        assert_eq!(counts[(2, 2)], 5);
+        for ((row, col), &count) in counts.indexed_iter() {
+            assert_eq!(
+                count,
+                swarm.covering([10 + col as i64, 10 + row as i64, 12]).len()
+            );
+        }
+        let rendered = render_heatmap(&counts);
+        assert_eq!(rendered.lines().count(), 5);
+        assert_eq!(
+            rendered.lines().nth(2).and_then(|l| l.chars().nth(2)),
+            Some('@')
+        );
+    }
+
     #[test]
     fn test_real_p2() {
         assert_eq!(calculate_p2(&parse(REAL_DATA)), 113066145)
@@ -1,40 +1,84 @@
 use bitvec::prelude::*;
 
-/// Emulates part of the interface of a HashSet<(usize, usize)>, with bounded
-/// allowed positions, using a bitvec as underlying storage.
-pub struct BitVecSet2D {
+/// An integer coordinate which can be stored relative to an origin.
+pub trait Coord: Copy + PartialOrd {
+    /// Distance from `origin` up to `self`, if `self` is not below it.
+    fn index_from(self, origin: Self) -> Option<usize>;
+    fn offset_by(self, n: usize) -> Self;
+}
+
+macro_rules! impl_coord {
+    ($($t:ty),*) => {
+        $(
+            impl Coord for $t {
+                fn index_from(self, origin: Self) -> Option<usize> {
+                    self.checked_sub(origin).and_then(|d| usize::try_from(d).ok())
+                }
+
+                fn offset_by(self, n: usize) -> Self {
+                    self + n as $t
+                }
+            }
+        )*
+    };
+}
+
+impl_coord!(usize, isize, i32, i64);
+
+/// Emulates part of the interface of a HashSet<(T, T)>, with bounded allowed
+/// positions, using a bitvec as underlying storage.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct BitVecSet2D<T: Coord = usize> {
     pub bv: BitVec,
     pub bounds: (usize, usize),
+    pub origin: (T, T),
 }
 
-impl BitVecSet2D {
+impl BitVecSet2D<usize> {
     pub fn new(bounds: (usize, usize)) -> BitVecSet2D {
+        BitVecSet2D::with_origin((0, 0), bounds)
+    }
+}
+
+impl<T: Coord> BitVecSet2D<T> {
+    pub fn with_origin(origin: (T, T), bounds: (usize, usize)) -> BitVecSet2D<T> {
         BitVecSet2D {
             bv: bitvec![usize, Lsb0; 0; bounds.0 * bounds.1],
             bounds,
+            origin,
         }
     }
 
-    fn idx(&self, pos: &(usize, usize)) -> usize {
-        pos.0 * self.bounds.1 + pos.1
+    /// A new empty set covering the same positions as this one.
+    pub fn empty_like(&self) -> BitVecSet2D<T> {
+        BitVecSet2D::with_origin(self.origin, self.bounds)
     }
 
-    pub fn get(&self, pos: &(usize, usize)) -> Option<bool> {
-        if pos.0 >= self.bounds.0 || pos.1 >= self.bounds.1 {
-            None
-        } else {
-            self.bv.get(self.idx(pos)).map(|b| *b)
-        }
+    fn local(&self, pos: &(T, T)) -> Option<(usize, usize)> {
+        let y = pos.0.index_from(self.origin.0)?;
+        let x = pos.1.index_from(self.origin.1)?;
+        (y < self.bounds.0 && x < self.bounds.1).then_some((y, x))
     }
 
-    pub fn contains(&self, pos: &(usize, usize)) -> bool {
+    fn global(&self, (y, x): (usize, usize)) -> (T, T) {
+        (self.origin.0.offset_by(y), self.origin.1.offset_by(x))
+    }
+
+    fn idx(&self, (y, x): (usize, usize)) -> usize {
+        y * self.bounds.1 + x
+    }
+
+    pub fn get(&self, pos: &(T, T)) -> Option<bool> {
+        self.local(pos)
+            .and_then(|local| self.bv.get(self.idx(local)).map(|b| *b))
+    }
+
+    pub fn contains(&self, pos: &(T, T)) -> bool {
         self.get(pos).is_some_and(|b| b)
     }
 
-    pub fn insert(&mut self, pos: (usize, usize)) -> bool {
-        debug_assert!(pos.0 < self.bounds.0);
-        debug_assert!(pos.1 < self.bounds.1);
-        let idx = self.idx(&pos);
+    pub fn insert(&mut self, pos: (T, T)) -> bool {
+        let idx = self.idx(self.local(&pos).expect("position out of bounds"));
         !self
             .bv
             .get_mut(idx)
@@ -42,10 +86,8 @@ impl BitVecSet2D {
             .replace(true)
     }
 
-    pub fn remove(&mut self, pos: (usize, usize)) -> bool {
-        debug_assert!(pos.0 < self.bounds.0);
-        debug_assert!(pos.1 < self.bounds.1);
-        let idx = self.idx(&pos);
+    pub fn remove(&mut self, pos: (T, T)) -> bool {
+        let idx = self.idx(self.local(&pos).expect("position out of bounds"));
         self.bv
             .get_mut(idx)
             .expect("invalid bv index")
@@ -64,9 +106,482 @@ impl BitVecSet2D {
         self.bv.fill(false);
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = (usize, usize)> + use<'_> {
+    pub fn iter(&self) -> impl Iterator<Item = (T, T)> + use<'_, T> {
         self.bv
             .iter_ones()
-            .map(|b| (b / self.bounds.1, b % self.bounds.1))
+            .map(|b| self.global((b / self.bounds.1, b % self.bounds.1)))
+    }
+
+    fn zip_words(&mut self, other: &BitVecSet2D<T>, f: impl Fn(usize, usize) -> usize) {
+        assert!(
+            self.bounds == other.bounds && self.origin == other.origin,
+            "sets must cover the same positions"
+        );
+        self.bv
+            .as_raw_mut_slice()
+            .iter_mut()
+            .zip(other.bv.as_raw_slice())
+            .for_each(|(a, &b)| *a = f(*a, b));
+    }
+
+    pub fn union_with(&mut self, other: &BitVecSet2D<T>) {
+        self.zip_words(other, |a, b| a | b);
+    }
+
+    pub fn intersect_with(&mut self, other: &BitVecSet2D<T>) {
+        self.zip_words(other, |a, b| a & b);
+    }
+
+    pub fn difference_with(&mut self, other: &BitVecSet2D<T>) {
+        self.zip_words(other, |a, b| a & !b);
+    }
+
+    pub fn union(&self, other: &BitVecSet2D<T>) -> BitVecSet2D<T> {
+        let mut result = self.clone();
+        result.union_with(other);
+        result
+    }
+
+    pub fn intersection(&self, other: &BitVecSet2D<T>) -> BitVecSet2D<T> {
+        let mut result = self.clone();
+        result.intersect_with(other);
+        result
+    }
+
+    pub fn difference(&self, other: &BitVecSet2D<T>) -> BitVecSet2D<T> {
+        let mut result = self.clone();
+        result.difference_with(other);
+        result
+    }
+
+    /// The bits of a single row, indexed by offset from `origin.1`.
+    pub fn row(&self, y: T) -> Option<&BitSlice> {
+        let y = y.index_from(self.origin.0).filter(|&y| y < self.bounds.0)?;
+        Some(&self.bv[y * self.bounds.1..(y + 1) * self.bounds.1])
+    }
+
+    pub fn row_mut(&mut self, y: T) -> Option<&mut BitSlice> {
+        let y = y.index_from(self.origin.0).filter(|&y| y < self.bounds.0)?;
+        Some(&mut self.bv[y * self.bounds.1..(y + 1) * self.bounds.1])
+    }
+
+    /// Counts members in the rectangle with inclusive corners `min` and `max`,
+    /// ignoring any part of it which lies outside the set's bounds.
+    pub fn count_in_rect(&self, min: (T, T), max: (T, T)) -> usize {
+        let clamped = |lo: T, hi: T, origin: T, bound: usize| {
+            let index = |v: T| {
+                v.index_from(origin)
+                    .unwrap_or(if v < origin { 0 } else { usize::MAX })
+            };
+            let start = index(lo).min(bound);
+            let end = if hi < origin {
+                0
+            } else {
+                index(hi).saturating_add(1).min(bound)
+            };
+            start..end.max(start)
+        };
+        let ys = clamped(min.0, max.0, self.origin.0, self.bounds.0);
+        let xs = clamped(min.1, max.1, self.origin.1, self.bounds.1);
+
+        ys.map(|y| {
+            let start = y * self.bounds.1;
+            self.bv[start + xs.start..start + xs.end].count_ones()
+        })
+        .sum()
+    }
+
+    /// The positions reachable from `start` by orthogonal steps through
+    /// non-members, staying within bounds.
+    pub fn flood_fill(&self, start: (T, T)) -> BitVecSet2D<T> {
+        let mut filled = self.empty_like();
+        let Some(start) = self.local(&start) else {
+            return filled;
+        };
+        if self.bv[self.idx(start)] {
+            return filled;
+        }
+
+        let mut stack = vec![start];
+        filled.bv.set(self.idx(start), true);
+        while let Some((y, x)) = stack.pop() {
+            [
+                (y.wrapping_sub(1), x),
+                (y, x.wrapping_sub(1)),
+                (y, x + 1),
+                (y + 1, x),
+            ]
+            .into_iter()
+            .filter(|&(ny, nx)| ny < self.bounds.0 && nx < self.bounds.1)
+            .for_each(|n| {
+                let idx = self.idx(n);
+                if !self.bv[idx] && !filled.bv[idx] {
+                    filled.bv.set(idx, true);
+                    stack.push(n);
+                }
+            });
+        }
+        filled
+    }
+}
+
+/// Emulates part of the interface of a HashSet<[usize; N]>, with bounded
+/// allowed positions, using a bitvec as underlying storage.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct BitVecSetND<const N: usize> {
+    pub bv: BitVec,
+    pub bounds: [usize; N],
+}
+
+pub type BitVecSet3D = BitVecSetND<3>;
+
+impl<const N: usize> BitVecSetND<N> {
+    pub fn new(bounds: [usize; N]) -> BitVecSetND<N> {
+        BitVecSetND {
+            bv: bitvec![usize, Lsb0; 0; bounds.iter().product()],
+            bounds,
+        }
+    }
+
+    fn idx(&self, pos: &[usize; N]) -> Option<usize> {
+        pos.iter()
+            .zip(self.bounds)
+            .try_fold(0, |idx, (&p, bound)| (p < bound).then_some(idx * bound + p))
+    }
+
+    pub fn get(&self, pos: &[usize; N]) -> Option<bool> {
+        self.idx(pos).and_then(|idx| self.bv.get(idx).map(|b| *b))
+    }
+
+    pub fn contains(&self, pos: &[usize; N]) -> bool {
+        self.get(pos).is_some_and(|b| b)
+    }
+
+    pub fn insert(&mut self, pos: [usize; N]) -> bool {
+        let idx = self.idx(&pos).expect("position out of bounds");
+        !self
+            .bv
+            .get_mut(idx)
+            .expect("invalid bv index")
+            .replace(true)
+    }
+
+    pub fn remove(&mut self, pos: [usize; N]) -> bool {
+        let idx = self.idx(&pos).expect("position out of bounds");
+        self.bv
+            .get_mut(idx)
+            .expect("invalid bv index")
+            .replace(false)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bv.not_any()
+    }
+
+    pub fn len(&self) -> usize {
+        self.bv.count_ones()
+    }
+
+    pub fn clear(&mut self) {
+        self.bv.fill(false);
+    }
+
+    /// Changes the bounds, keeping the members which lie within the new ones.
+    pub fn resize(&mut self, bounds: [usize; N]) {
+        let mut resized = BitVecSetND::new(bounds);
+        for pos in self
+            .iter()
+            .filter(|pos| pos.iter().zip(bounds).all(|(&p, b)| p < b))
+        {
+            resized.insert(pos);
+        }
+        *self = resized;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = [usize; N]> + use<'_, N> {
+        self.bv.iter_ones().map(|mut b| {
+            let mut pos = [0; N];
+            for (p, bound) in pos.iter_mut().zip(self.bounds).rev() {
+                *p = b % bound;
+                b /= bound;
+            }
+            pos
+        })
+    }
+}
+
+/// Emulates part of the interface of a HashSet<i64>, using whole u64 words
+/// as underlying storage. Empty words are trimmed so equal sets compare equal.
+#[derive(PartialEq, Eq, Debug, Clone, Hash, Default)]
+pub struct BitVecRow {
+    pub bv: BitVec<u64, Lsb0>,
+    pub offset: i64,
+}
+
+impl BitVecRow {
+    pub fn new() -> BitVecRow {
+        BitVecRow::default()
+    }
+
+    /// Builds a row from raw words, dropping empty words at either end.
+    pub fn from_words(mut words: Vec<u64>, offset: i64) -> BitVecRow {
+        let leading = words.iter().take_while(|&&w| w == 0).count();
+        let trailing = words[leading..]
+            .iter()
+            .rev()
+            .take_while(|&&w| w == 0)
+            .count();
+        words.truncate(words.len() - trailing);
+        words.drain(..leading);
+        if words.is_empty() {
+            return BitVecRow::new();
+        }
+
+        BitVecRow {
+            bv: BitVec::from_vec(words),
+            offset: offset + 64 * leading as i64,
+        }
+    }
+
+    pub fn words(&self) -> &[u64] {
+        self.bv.as_raw_slice()
+    }
+
+    fn idx(&self, pos: i64) -> Option<usize> {
+        usize::try_from(pos - self.offset)
+            .ok()
+            .filter(|&i| i < self.bv.len())
+    }
+
+    pub fn contains(&self, pos: &i64) -> bool {
+        self.idx(*pos).is_some_and(|i| self.bv[i])
+    }
+
+    pub fn insert(&mut self, pos: i64) -> bool {
+        if self.bv.is_empty() {
+            self.offset = pos.div_euclid(64) * 64;
+        }
+        while pos < self.offset {
+            let mut grown = bitvec![u64, Lsb0; 0; 64];
+            grown.append(&mut self.bv);
+            self.bv = grown;
+            self.offset -= 64;
+        }
+        while pos >= self.offset + self.bv.len() as i64 {
+            self.bv.extend_from_raw_slice(&[0]);
+        }
+        let idx = (pos - self.offset) as usize;
+        !self
+            .bv
+            .get_mut(idx)
+            .expect("invalid bv index")
+            .replace(true)
+    }
+
+    pub fn remove(&mut self, pos: i64) -> bool {
+        let Some(idx) = self.idx(pos) else {
+            return false;
+        };
+        let removed = self
+            .bv
+            .get_mut(idx)
+            .expect("invalid bv index")
+            .replace(false);
+        let words = self.words();
+        if words.first() == Some(&0) || words.last() == Some(&0) {
+            *self = BitVecRow::from_words(std::mem::take(&mut self.bv).into_vec(), self.offset);
+        }
+        removed
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bv.not_any()
+    }
+
+    pub fn len(&self) -> usize {
+        self.bv.count_ones()
+    }
+
+    pub fn clear(&mut self) {
+        self.bv.clear();
+        self.offset = 0;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = i64> + use<'_> {
+        self.bv.iter_ones().map(|b| self.offset + b as i64)
+    }
+}
+
+impl FromIterator<i64> for BitVecRow {
+    fn from_iter<T: IntoIterator<Item = i64>>(iter: T) -> Self {
+        let mut row = BitVecRow::new();
+        iter.into_iter().for_each(|pos| {
+            row.insert(pos);
+        });
+        row
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn random_set(seed: u64, origin: (i64, i64), bounds: (usize, usize)) -> BitVecSet2D<i64> {
+        let mut rng = fastrand::Rng::with_seed(seed);
+        let mut set = BitVecSet2D::with_origin(origin, bounds);
+        for y in 0..bounds.0 as i64 {
+            for x in 0..bounds.1 as i64 {
+                if rng.u8(..3) == 0 {
+                    set.insert((origin.0 + y, origin.1 + x));
+                }
+            }
+        }
+        set
+    }
+
+    fn to_hash_set(set: &BitVecSet2D<i64>) -> HashSet<(i64, i64)> {
+        set.iter().collect()
+    }
+
+    #[test]
+    fn test_set_algebra() {
+        for seed in 0..50 {
+            let origin = (seed as i64 - 25, -(seed as i64 * 7));
+            let bounds = (1 + seed as usize % 9, 1 + seed as usize * 5 % 131);
+            let a = random_set(seed, origin, bounds);
+            let b = random_set(seed + 1000, origin, bounds);
+            let (ha, hb) = (to_hash_set(&a), to_hash_set(&b));
+
+            let union = a.union(&b);
+            let intersection = a.intersection(&b);
+            let difference = a.difference(&b);
+            assert_eq!(to_hash_set(&union), &ha | &hb);
+            assert_eq!(to_hash_set(&intersection), &ha & &hb);
+            assert_eq!(to_hash_set(&difference), &ha - &hb);
+
+            let mut in_place = a.clone();
+            in_place.union_with(&b);
+            assert_eq!(in_place, union);
+            let mut in_place = a.clone();
+            in_place.intersect_with(&b);
+            assert_eq!(in_place, intersection);
+            let mut in_place = a.clone();
+            in_place.difference_with(&b);
+            assert_eq!(in_place, difference);
+            assert_eq!(difference.len() + intersection.len(), a.len());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "sets must cover the same positions")]
+    fn test_set_algebra_mismatched_origins() {
+        let a = BitVecSet2D::with_origin((0i64, 0), (3, 3));
+        let b = BitVecSet2D::with_origin((0i64, 1), (3, 3));
+        a.union(&b);
+    }
+
+    #[test]
+    fn test_rows() {
+        let mut set = BitVecSet2D::with_origin((-2i64, 10), (3, 4));
+        set.insert((-1, 11));
+        set.insert((-1, 13));
+        set.insert((0, 10));
+
+        let row = set.row(-1).unwrap();
+        assert_eq!(row.len(), 4);
+        assert_eq!(row.iter_ones().collect::<Vec<_>>(), [1, 3]);
+        assert!(set.row(-2).unwrap().not_any());
+        assert_eq!(set.row(-3), None);
+        assert_eq!(set.row(1), None);
+
+        set.row_mut(-2).unwrap().fill(true);
+        set.row_mut(0).unwrap().set(0, false);
+        assert_eq!(set.row_mut(1), None);
+        assert_eq!(
+            set.iter().collect::<Vec<_>>(),
+            [(-2, 10), (-2, 11), (-2, 12), (-2, 13), (-1, 11), (-1, 13)]
+        );
+    }
+
+    #[test]
+    fn test_row_equality() {
+        let mut row = [-100, 5, 200].into_iter().collect::<BitVecRow>();
+        row.remove(-100);
+        row.remove(200);
+        assert_eq!(row, [5].into_iter().collect::<BitVecRow>());
+        assert_eq!(row.words().len(), 1);
+
+        row.remove(5);
+        assert_eq!(row, BitVecRow::new());
+        row.insert(-70);
+        assert_eq!(row, [-70].into_iter().collect::<BitVecRow>());
+    }
+
+    #[test]
+    fn test_count_in_rect() {
+        let mut set = BitVecSet2D::new((3, 3));
+        set.insert((0, 0));
+        set.insert((1, 2));
+        set.insert((2, 2));
+        assert_eq!(set.count_in_rect((0, 0), (2, usize::MAX)), 3);
+        assert_eq!(set.count_in_rect((1, 1), (usize::MAX, usize::MAX)), 2);
+        assert_eq!(set.count_in_rect((0, 0), (0, 1)), 1);
+        assert_eq!(set.count_in_rect((2, 0), (1, 2)), 0);
+        assert_eq!(set.count_in_rect((5, 5), (9, 9)), 0);
+
+        let mut set = BitVecSet2D::with_origin((-5i64, -5), (3, 3));
+        set.insert((-3, -3));
+        assert_eq!(
+            set.count_in_rect((i64::MIN, i64::MIN), (i64::MAX, i64::MAX)),
+            1
+        );
+        assert_eq!(set.count_in_rect((i64::MIN, i64::MIN), (-4, i64::MAX)), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "position out of bounds")]
+    fn test_insert_out_of_bounds() {
+        BitVecSet2D::with_origin((-1i64, -1), (2, 2)).insert((-2, 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "position out of bounds")]
+    fn test_remove_out_of_bounds() {
+        BitVecSet2D::new((2, 2)).remove((0, 2));
+    }
+
+    #[test]
+    fn test_flood_fill() {
+        // A wall enclosing the middle cell, with a gap in the bottom right.
+        let mut walls = BitVecSet2D::with_origin((10i64, -10), (5, 5));
+        for (y, x) in [
+            (11, -9),
+            (11, -8),
+            (11, -7),
+            (12, -9),
+            (12, -7),
+            (13, -9),
+            (13, -8),
+        ] {
+            walls.insert((y, x));
+        }
+
+        let inside = walls.flood_fill((12, -8));
+        assert_eq!(inside.iter().collect::<Vec<_>>(), [(12, -8)]);
+
+        let outside = walls.flood_fill((10, -10));
+        assert_eq!(outside.len(), 25 - walls.len() - 1);
+        assert!(outside.intersection(&walls).is_empty());
+        assert!(!outside.contains(&(12, -8)));
+
+        walls.insert((13, -7));
+        walls.remove((12, -7));
+        let joined = walls.flood_fill((12, -8));
+        assert!(joined.contains(&(10, -10)));
+        assert_eq!(joined.len(), 25 - walls.len());
+
+        assert!(walls.flood_fill((11, -9)).is_empty());
+        assert!(walls.flood_fill((9, -10)).is_empty());
     }
 }
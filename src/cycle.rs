@@ -0,0 +1,223 @@
+use ahash::AHashMap;
+use std::hash::Hash;
+
+/// State `start + length` equals state `start`, for the smallest `length`.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub struct Cycle {
+    pub start: usize,
+    pub length: usize,
+}
+
+impl Cycle {
+    /// The earliest generation whose state is equal to the state at `generation`.
+    pub fn equivalent_generation(&self, generation: usize) -> usize {
+        if generation < self.start {
+            generation
+        } else {
+            self.start + (generation - self.start) % self.length
+        }
+    }
+
+    /// Projects `values`, known up to `start + length`, assuming they change
+    /// by a fixed amount per cycle.
+    pub fn extrapolate(&self, values: &[i64], generation: usize) -> i64 {
+        debug_assert!(values.len() > self.start + self.length);
+        let equivalent = self.equivalent_generation(generation);
+        let cycles = ((generation - equivalent) / self.length) as i64;
+        let per_cycle = values[self.start + self.length] - values[self.start];
+        values[equivalent] + cycles * per_cycle
+    }
+}
+
+/// Steps from `initial` until a state's key repeats, returning the cycle and
+/// every state up to the first repeat.
+pub fn find_cycle<S, K, F, KF>(initial: S, mut step: F, mut key: KF) -> (Cycle, Vec<S>)
+where
+    K: Hash + Eq,
+    F: FnMut(&S) -> S,
+    KF: FnMut(&S) -> K,
+{
+    let mut seen = AHashMap::default();
+    seen.insert(key(&initial), 0);
+    let mut history = vec![initial];
+
+    loop {
+        let next = step(history.last().expect("nonempty"));
+        let generation = history.len();
+        let prev = seen.insert(key(&next), generation);
+        history.push(next);
+
+        if let Some(start) = prev {
+            let cycle = Cycle {
+                start,
+                length: generation - start,
+            };
+            return (cycle, history);
+        }
+    }
+}
+
+/// As [`find_cycle`], but for states which are their own key, so each is
+/// stored only once.
+pub fn find_cycle_by_state<S, F>(initial: S, mut step: F) -> (Cycle, Vec<S>)
+where
+    S: Hash + Eq,
+    F: FnMut(&S) -> S,
+{
+    let hasher = ahash::RandomState::new();
+    let mut seen: AHashMap<u64, Vec<usize>> = AHashMap::default();
+    seen.entry(hasher.hash_one(&initial)).or_default().push(0);
+    let mut history = vec![initial];
+
+    loop {
+        let next = step(history.last().expect("nonempty"));
+        let generation = history.len();
+        let candidates = seen.entry(hasher.hash_one(&next)).or_default();
+        let prev = candidates.iter().copied().find(|&i| history[i] == next);
+        candidates.push(generation);
+        history.push(next);
+
+        if let Some(start) = prev {
+            let cycle = Cycle {
+                start,
+                length: generation - start,
+            };
+            return (cycle, history);
+        }
+    }
+}
+
+/// Floyd's cycle detection, holding only a few states in memory.
+pub fn floyd<S, F>(initial: S, mut step: F) -> Cycle
+where
+    S: PartialEq,
+    F: FnMut(&S) -> S,
+{
+    let mut tortoise = step(&initial);
+    let mut hare = step(&tortoise);
+    while tortoise != hare {
+        tortoise = step(&tortoise);
+        let halfway = step(&hare);
+        hare = step(&halfway);
+    }
+
+    let mut start = 0;
+    tortoise = initial;
+    while tortoise != hare {
+        tortoise = step(&tortoise);
+        hare = step(&hare);
+        start += 1;
+    }
+
+    let mut length = 1;
+    hare = step(&tortoise);
+    while tortoise != hare {
+        hare = step(&hare);
+        length += 1;
+    }
+
+    Cycle { start, length }
+}
+
+/// Like [`floyd`], but usually needs fewer steps.
+pub fn brent<S, F>(initial: S, mut step: F) -> Cycle
+where
+    S: PartialEq + Clone,
+    F: FnMut(&S) -> S,
+{
+    let mut power = 1;
+    let mut length = 1;
+    let mut tortoise = initial.clone();
+    let mut hare = step(&initial);
+    while tortoise != hare {
+        if power == length {
+            tortoise = hare.clone();
+            power *= 2;
+            length = 0;
+        }
+        hare = step(&hare);
+        length += 1;
+    }
+
+    let mut tortoise = initial.clone();
+    let mut hare = initial;
+    for _ in 0..length {
+        hare = step(&hare);
+    }
+
+    let mut start = 0;
+    while tortoise != hare {
+        tortoise = step(&tortoise);
+        hare = step(&hare);
+        start += 1;
+    }
+
+    Cycle { start, length }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_fn(seed: u64, size: usize) -> Vec<usize> {
+        let mut rng = fastrand::Rng::with_seed(seed);
+        (0..size).map(|_| rng.usize(..size)).collect()
+    }
+
+    #[test]
+    fn test_detectors_agree() {
+        for seed in 0..200 {
+            let size = 1 + seed as usize % 50;
+            let f = random_fn(seed, size);
+            let step = |&s: &usize| f[s];
+
+            let (cycle, history) = find_cycle(0, step, |&s| s);
+            assert_eq!(history.len(), cycle.start + cycle.length + 1);
+            assert_eq!(history[cycle.start], history[cycle.start + cycle.length]);
+            assert_eq!(find_cycle_by_state(0, step), (cycle, history));
+            assert_eq!(floyd(0, step), cycle);
+            assert_eq!(brent(0, step), cycle);
+        }
+    }
+
+    #[test]
+    fn test_extrapolate() {
+        for seed in 0..50 {
+            let f = random_fn(seed, 30);
+            // A running total of the states drifts by a fixed amount per cycle.
+            let mut states = vec![0];
+            let mut totals = vec![0i64];
+            for _ in 0..2000 {
+                let s = f[*states.last().expect("nonempty")];
+                totals.push(totals.last().expect("nonempty") + s as i64);
+                states.push(s);
+            }
+
+            let (cycle, _) = find_cycle(0, |&s: &usize| f[s], |&s| s);
+            let known = &totals[..=cycle.start + cycle.length];
+            for generation in [0, 1, 10, 100, 1000, 2000] {
+                assert_eq!(
+                    states[cycle.equivalent_generation(generation)],
+                    states[generation]
+                );
+                assert_eq!(cycle.extrapolate(known, generation), totals[generation]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_colliding_hashes() {
+        // Every state hashes the same, so only equality tells them apart.
+        #[derive(PartialEq, Eq)]
+        struct Colliding(usize);
+        impl Hash for Colliding {
+            fn hash<H: std::hash::Hasher>(&self, _: &mut H) {}
+        }
+
+        let f = random_fn(7, 40);
+        let (expected, _) = find_cycle(0, |&s: &usize| f[s], |&s| s);
+        let (cycle, history) = find_cycle_by_state(Colliding(0), |s| Colliding(f[s.0]));
+        assert_eq!(cycle, expected);
+        assert_eq!(history.len(), cycle.start + cycle.length + 1);
+    }
+}
@@ -0,0 +1,357 @@
+//! Integer points, boxes and Manhattan balls in three dimensions.
+
+pub type Point = [i64; 3];
+
+pub fn manhattan(a: Point, b: Point) -> u64 {
+    (0..3).map(|i| a[i].abs_diff(b[i])).sum()
+}
+
+/// x + y + z, x + y - z, x - y + z and -x + y + z, in which Manhattan balls
+/// become boxes.
+pub fn rotate([x, y, z]: Point) -> [i64; 4] {
+    [x + y + z, x + y - z, x - y + z, -x + y + z]
+}
+
+/// An axis-aligned box of integer points, with inclusive corners.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Hash)]
+pub struct Cuboid {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl Cuboid {
+    pub fn point(p: Point) -> Cuboid {
+        Cuboid { min: p, max: p }
+    }
+
+    pub fn contains(&self, p: Point) -> bool {
+        (0..3).all(|i| self.min[i] <= p[i] && p[i] <= self.max[i])
+    }
+
+    pub fn is_point(&self) -> bool {
+        self.min == self.max
+    }
+
+    pub fn volume(&self) -> u128 {
+        (0..3)
+            .map(|i| u128::from(self.min[i].abs_diff(self.max[i])) + 1)
+            .product()
+    }
+
+    /// Corners may repeat if the box is flat.
+    pub fn corners(&self) -> impl Iterator<Item = Point> + '_ {
+        (0..8).map(|n: usize| std::array::from_fn(|i| [self.min[i], self.max[i]][(n >> i) & 1]))
+    }
+
+    /// The smallest Manhattan distance from any point of the box to `p`.
+    pub fn distance_to(&self, p: Point) -> u64 {
+        (0..3)
+            .map(|i| {
+                if p[i] < self.min[i] {
+                    self.min[i].abs_diff(p[i])
+                } else if p[i] > self.max[i] {
+                    p[i].abs_diff(self.max[i])
+                } else {
+                    0
+                }
+            })
+            .sum()
+    }
+
+    pub fn union(&self, other: &Cuboid) -> Cuboid {
+        Cuboid {
+            min: std::array::from_fn(|i| self.min[i].min(other.min[i])),
+            max: std::array::from_fn(|i| self.max[i].max(other.max[i])),
+        }
+    }
+
+    pub fn split(&self) -> [Cuboid; 2] {
+        let axis = (0..3)
+            .max_by_key(|&i| self.min[i].abs_diff(self.max[i]))
+            .expect("three axes");
+        // Rounds towards the lower corner even when it is negative.
+        let mid = self.min[axis] + (self.max[axis] - self.min[axis]) / 2;
+        let (mut lower, mut upper) = (*self, *self);
+        lower.max[axis] = mid;
+        upper.min[axis] = mid + 1;
+        [lower, upper]
+    }
+}
+
+/// The integer points within Manhattan distance `radius` of `center`.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Hash)]
+pub struct Octahedron {
+    pub center: Point,
+    pub radius: u64,
+}
+
+impl Octahedron {
+    pub fn contains(&self, p: Point) -> bool {
+        manhattan(self.center, p) <= self.radius
+    }
+
+    pub fn intersects(&self, other: &Octahedron) -> bool {
+        manhattan(self.center, other.center) <= self.radius + other.radius
+    }
+
+    pub fn intersects_box(&self, b: &Cuboid) -> bool {
+        b.distance_to(self.center) <= self.radius
+    }
+
+    // Distance is convex, so checking the corners is enough.
+    pub fn contains_box(&self, b: &Cuboid) -> bool {
+        b.corners().all(|c| self.contains(c))
+    }
+
+    pub fn bounding_box(&self) -> Cuboid {
+        let r = self.radius as i64;
+        Cuboid {
+            min: self.center.map(|c| c - r),
+            max: self.center.map(|c| c + r),
+        }
+    }
+
+    pub fn rotated(&self) -> RotatedBox {
+        let r = self.radius as i64;
+        let center = rotate(self.center);
+        RotatedBox {
+            min: center.map(|c| c - r),
+            max: center.map(|c| c + r),
+        }
+    }
+
+    pub fn point_count(&self) -> u128 {
+        self.rotated().point_count()
+    }
+}
+
+/// The integer points whose rotated coordinates lie between `min` and `max`,
+/// inclusive. Any intersection of octahedra has this shape.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Hash)]
+pub struct RotatedBox {
+    pub min: [i64; 4],
+    pub max: [i64; 4],
+}
+
+impl RotatedBox {
+    pub fn contains(&self, p: Point) -> bool {
+        let r = rotate(p);
+        (0..4).all(|i| self.min[i] <= r[i] && r[i] <= self.max[i])
+    }
+
+    pub fn intersection(&self, other: &RotatedBox) -> RotatedBox {
+        RotatedBox {
+            min: std::array::from_fn(|i| self.min[i].max(other.min[i])),
+            max: std::array::from_fn(|i| self.max[i].min(other.max[i])),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.point_count() == 0
+    }
+
+    /// None if the region is empty.
+    pub fn distance_to(&self, p: Point) -> Option<u64> {
+        if self.is_empty() {
+            return None;
+        }
+        let touches = |radius| {
+            let ball = Octahedron { center: p, radius }.rotated();
+            !self.intersection(&ball).is_empty()
+        };
+
+        let r = rotate(p);
+        let mut high = (0..4)
+            .map(|i| self.min[i].abs_diff(r[i]).max(self.max[i].abs_diff(r[i])))
+            .max()
+            .expect("four coordinates");
+        let mut low = 0;
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if touches(mid) {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        Some(low)
+    }
+
+    /// The exact number of integer points in the region.
+    pub fn point_count(&self) -> u128 {
+        (0..2).map(|parity| self.count_with_parity(parity)).sum()
+    }
+
+    // The last three rotated coordinates of these points are each 2k + parity,
+    // and their sum is the first.
+    fn count_with_parity(&self, parity: i64) -> u128 {
+        let floor_half = |n: i64| i128::from(n.div_euclid(2));
+        let ceil_half = |n: i64| -i128::from((-n).div_euclid(2));
+
+        let mut lens = [0; 3];
+        let mut low_sum = 0;
+        for (i, len) in lens.iter_mut().enumerate() {
+            let low = ceil_half(self.min[i + 1] - parity);
+            let high = floor_half(self.max[i + 1] - parity);
+            if low > high {
+                return 0;
+            }
+            *len = high - low + 1;
+            low_sum += low;
+        }
+        let sum_low = ceil_half(self.min[0] - 3 * parity) - low_sum;
+        let sum_high = floor_half(self.max[0] - 3 * parity) - low_sum;
+        if sum_low > sum_high {
+            return 0;
+        }
+
+        let count = count_sums_up_to(lens, sum_high) - count_sums_up_to(lens, sum_low - 1);
+        count as u128
+    }
+}
+
+// Triples with each part in 0..lens[i] and sum at most n, by
+// inclusion-exclusion.
+fn count_sums_up_to(lens: [i128; 3], n: i128) -> i128 {
+    let unbounded = |n: i128| {
+        if n < 0 {
+            0
+        } else {
+            (n + 1) * (n + 2) * (n + 3) / 6
+        }
+    };
+
+    (0..8)
+        .map(|mask: usize| {
+            let excess: i128 = (0..3).filter(|i| mask >> i & 1 == 1).map(|i| lens[i]).sum();
+            let term = unbounded(n - excess);
+            if mask.count_ones().is_multiple_of(2) {
+                term
+            } else {
+                -term
+            }
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::iproduct;
+
+    fn point(rng: &mut fastrand::Rng, reach: i64) -> Point {
+        std::array::from_fn(|_| rng.i64(-reach..=reach))
+    }
+
+    fn octahedron(rng: &mut fastrand::Rng) -> Octahedron {
+        Octahedron {
+            center: point(rng, 5),
+            radius: rng.u64(..6),
+        }
+    }
+
+    fn cuboid(rng: &mut fastrand::Rng) -> Cuboid {
+        let (a, b) = (point(rng, 8), point(rng, 8));
+        Cuboid {
+            min: std::array::from_fn(|i| a[i].min(b[i])),
+            max: std::array::from_fn(|i| a[i].max(b[i])),
+        }
+    }
+
+    fn all_points(reach: i64) -> impl Iterator<Item = Point> {
+        iproduct!(-reach..=reach, -reach..=reach, -reach..=reach).map(|(x, y, z)| [x, y, z])
+    }
+
+    #[test]
+    fn test_rotated_matches_manhattan() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        for _ in 0..100 {
+            let ball = octahedron(&mut rng);
+            let rotated = ball.rotated();
+            for p in all_points(12) {
+                assert_eq!(rotated.contains(p), ball.contains(p), "{ball:?} {p:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_intersections_match_enumeration() {
+        let mut rng = fastrand::Rng::with_seed(2);
+        for _ in 0..200 {
+            let (a, b, c) = (octahedron(&mut rng), octahedron(&mut rng), cuboid(&mut rng));
+            let points = all_points(16).collect::<Vec<_>>();
+
+            let shared = points.iter().any(|&p| a.contains(p) && b.contains(p));
+            assert_eq!(a.intersects(&b), shared, "{a:?} {b:?}");
+
+            let touching = points.iter().any(|&p| a.contains(p) && c.contains(p));
+            assert_eq!(a.intersects_box(&c), touching, "{a:?} {c:?}");
+
+            let inside = points.iter().all(|&p| !c.contains(p) || a.contains(p));
+            assert_eq!(a.contains_box(&c), inside, "{a:?} {c:?}");
+        }
+    }
+
+    #[test]
+    fn test_point_count_matches_enumeration() {
+        assert_eq!(
+            Octahedron {
+                center: [0; 3],
+                radius: 0
+            }
+            .point_count(),
+            1
+        );
+        assert_eq!(
+            Octahedron {
+                center: [0; 3],
+                radius: 1
+            }
+            .point_count(),
+            7
+        );
+        assert_eq!(
+            Octahedron {
+                center: [0; 3],
+                radius: 2
+            }
+            .point_count(),
+            25
+        );
+
+        let mut rng = fastrand::Rng::with_seed(3);
+        for _ in 0..200 {
+            let balls = (0..rng.usize(1..=3))
+                .map(|_| octahedron(&mut rng))
+                .collect::<Vec<_>>();
+            let region = balls
+                .iter()
+                .map(Octahedron::rotated)
+                .reduce(|a, b| a.intersection(&b))
+                .expect("nonempty");
+            let inside = all_points(12)
+                .filter(|&p| balls.iter().all(|b| b.contains(p)))
+                .collect::<Vec<_>>();
+            assert_eq!(region.point_count(), inside.len() as u128, "{balls:?}");
+            assert_eq!(region.is_empty(), inside.is_empty());
+
+            let p = point(&mut rng, 8);
+            let closest = inside.iter().map(|&q| manhattan(p, q)).min();
+            assert_eq!(region.distance_to(p), closest, "{balls:?} {p:?}");
+        }
+    }
+
+    #[test]
+    fn test_point_count_large() {
+        // (4r³ + 6r² + 8r + 3) / 3 points within distance r of a point.
+        let r = 1_000_000_000u128;
+        let ball = Octahedron {
+            center: [-7, 3, 123_456_789],
+            radius: r as u64,
+        };
+        assert_eq!(
+            ball.point_count(),
+            (4 * r * r * r + 6 * r * r + 8 * r + 3) / 3
+        );
+    }
+}
@@ -0,0 +1,383 @@
+use crate::automaton::Rule1D;
+use crate::bitvec_set::BitVecRow;
+use ahash::AHashMap;
+
+type NodeId = u32;
+
+/// A block of 2^level cells. Leaves are level 2, with bit `i` holding cell `i`.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+enum Node {
+    Leaf(u8),
+    Branch {
+        level: u8,
+        left: NodeId,
+        right: NodeId,
+    },
+}
+
+const LEAF_LEVEL: u8 = 2;
+
+/// Memoised ("hashlife") evaluation of a radius-2 1D automaton.
+pub struct HashLife1D {
+    rule: Rule1D,
+    nodes: Vec<Node>,
+    // Live cell count and sum of live cell offsets within each node.
+    population: Vec<u128>,
+    offset_sum: Vec<u128>,
+    interned: AHashMap<Node, NodeId>,
+    results: AHashMap<(NodeId, u8), NodeId>,
+    empty: Vec<NodeId>,
+    root: NodeId,
+    origin: i64,
+    pub generation: u64,
+}
+
+impl HashLife1D {
+    pub fn new(row: &BitVecRow, rule: Rule1D) -> HashLife1D {
+        let mut hl = HashLife1D {
+            rule,
+            nodes: vec![],
+            population: vec![],
+            offset_sum: vec![],
+            interned: AHashMap::default(),
+            results: AHashMap::default(),
+            empty: vec![],
+            root: 0,
+            origin: row.offset,
+            generation: 0,
+        };
+
+        let mut level_nodes = row
+            .bv
+            .chunks(1 << LEAF_LEVEL)
+            .map(|chunk| {
+                let bits = chunk.iter_ones().fold(0, |acc, i| acc | (1 << i));
+                hl.intern(Node::Leaf(bits))
+            })
+            .collect::<Vec<_>>();
+        if level_nodes.is_empty() {
+            level_nodes.push(hl.empty(LEAF_LEVEL));
+        }
+
+        let mut level = LEAF_LEVEL;
+        while level < 3 || level_nodes.len() > 1 {
+            if level_nodes.len() % 2 == 1 {
+                level_nodes.push(hl.empty(level));
+            }
+            level_nodes = level_nodes
+                .chunks(2)
+                .map(|pair| hl.join(pair[0], pair[1]))
+                .collect();
+            level += 1;
+        }
+        hl.root = level_nodes[0];
+        hl
+    }
+
+    fn intern(&mut self, node: Node) -> NodeId {
+        if let Some(&id) = self.interned.get(&node) {
+            return id;
+        }
+
+        let (population, offset_sum) = match node {
+            Node::Leaf(bits) => (
+                bits.count_ones() as u128,
+                (0..4).filter(|i| bits & (1 << i) != 0).sum(),
+            ),
+            Node::Branch { level, left, right } => {
+                let (l, r) = (left as usize, right as usize);
+                let half = 1u128 << (level - 1);
+                (
+                    self.population[l] + self.population[r],
+                    self.offset_sum[l] + self.offset_sum[r] + self.population[r] * half,
+                )
+            }
+        };
+
+        let id = self.nodes.len() as NodeId;
+        self.nodes.push(node);
+        self.population.push(population);
+        self.offset_sum.push(offset_sum);
+        self.interned.insert(node, id);
+        id
+    }
+
+    fn level(&self, id: NodeId) -> u8 {
+        match self.nodes[id as usize] {
+            Node::Leaf(_) => LEAF_LEVEL,
+            Node::Branch { level, .. } => level,
+        }
+    }
+
+    fn children(&self, id: NodeId) -> (NodeId, NodeId) {
+        match self.nodes[id as usize] {
+            Node::Leaf(_) => panic!("leaf has no children"),
+            Node::Branch { left, right, .. } => (left, right),
+        }
+    }
+
+    fn join(&mut self, left: NodeId, right: NodeId) -> NodeId {
+        debug_assert_eq!(self.level(left), self.level(right));
+        let level = self.level(left) + 1;
+        self.intern(Node::Branch { level, left, right })
+    }
+
+    fn empty(&mut self, level: u8) -> NodeId {
+        while self.empty.len() <= (level - LEAF_LEVEL) as usize {
+            let id = match self.empty.last() {
+                None => self.intern(Node::Leaf(0)),
+                Some(&e) => self.join(e, e),
+            };
+            self.empty.push(id);
+        }
+        self.empty[(level - LEAF_LEVEL) as usize]
+    }
+
+    fn is_empty(&self, id: NodeId) -> bool {
+        self.population[id as usize] == 0
+    }
+
+    /// The middle half of a node, without advancing it.
+    fn centre(&mut self, id: NodeId) -> NodeId {
+        let (l, r) = self.children(id);
+        match (self.nodes[l as usize], self.nodes[r as usize]) {
+            (Node::Leaf(lb), Node::Leaf(rb)) => {
+                self.intern(Node::Leaf((lb >> 2) | ((rb & 3) << 2)))
+            }
+            _ => {
+                let (_, lr) = self.children(l);
+                let (rl, _) = self.children(r);
+                self.join(lr, rl)
+            }
+        }
+    }
+
+    /// The middle half of a level-3 node advanced by a single generation.
+    fn base_result(&mut self, id: NodeId) -> NodeId {
+        let (l, r) = self.children(id);
+        let (Node::Leaf(lb), Node::Leaf(rb)) = (self.nodes[l as usize], self.nodes[r as usize])
+        else {
+            panic!("level 3 node should hold leaves");
+        };
+        let cells = lb as usize | ((rb as usize) << 4);
+
+        let bits = (0..4).fold(0, |acc, i| {
+            let window = (0..5).fold(0, |w, c| (w << 1) | ((cells >> (i + c)) & 1));
+            acc | (u8::from(self.rule.table()[window]) << i)
+        });
+        self.intern(Node::Leaf(bits))
+    }
+
+    /// The middle half of a node advanced by 2^j generations, where
+    /// j <= level - 3.
+    fn result(&mut self, id: NodeId, j: u8) -> NodeId {
+        let level = self.level(id);
+        debug_assert!(level >= 3 && j <= level - 3);
+
+        if let Some(&r) = self.results.get(&(id, j)) {
+            return r;
+        }
+
+        let result = if self.is_empty(id) {
+            self.empty(level - 1)
+        } else if level == 3 {
+            self.base_result(id)
+        } else {
+            let (l, r) = self.children(id);
+            let (_, b) = self.children(l);
+            let (c, _) = self.children(r);
+            let bc = self.join(b, c);
+
+            let sub_j = j.min(level - 4);
+            let r1 = self.result(l, sub_j);
+            let r2 = self.result(bc, sub_j);
+            let r3 = self.result(r, sub_j);
+            let left = self.join(r1, r2);
+            let right = self.join(r2, r3);
+
+            let (left, right) = if j == level - 3 {
+                (self.result(left, sub_j), self.result(right, sub_j))
+            } else {
+                (self.centre(left), self.centre(right))
+            };
+            self.join(left, right)
+        };
+
+        self.results.insert((id, j), result);
+        result
+    }
+
+    /// Pads the root with empty space on both sides, keeping it centred.
+    fn expand(&mut self) {
+        let level = self.level(self.root);
+        let (l, r) = self.children(self.root);
+        let e = self.empty(level - 1);
+        let left = self.join(e, l);
+        let right = self.join(r, e);
+        self.root = self.join(left, right);
+        self.origin -= 1 << (level - 1);
+    }
+
+    /// Whether all live cells lie within the middle quarter of the root.
+    fn is_centred(&self) -> bool {
+        if self.level(self.root) < 5 {
+            return false;
+        }
+        let (l, r) = self.children(self.root);
+        let (a, b) = self.children(l);
+        let (c, d) = self.children(r);
+        self.is_empty(a)
+            && self.is_empty(d)
+            && self.is_empty(self.children(b).0)
+            && self.is_empty(self.children(c).1)
+    }
+
+    /// Drops empty space around the pattern so the tree doesn't keep growing.
+    fn shrink(&mut self) {
+        while self.level(self.root) > 3 {
+            let (l, r) = self.children(self.root);
+            let (a, _) = self.children(l);
+            let (_, d) = self.children(r);
+            if !self.is_empty(a) || !self.is_empty(d) {
+                break;
+            }
+            let level = self.level(self.root);
+            self.root = self.centre(self.root);
+            self.origin += 1 << (level - 2);
+        }
+    }
+
+    /// Advances the row by 2^j generations.
+    pub fn step_pow2(&mut self, j: u8) {
+        // Keeps growth within the middle half which `result` returns.
+        while self.level(self.root) < j + 4 || !self.is_centred() {
+            self.expand();
+        }
+        let level = self.level(self.root);
+        self.root = self.result(self.root, j);
+        self.origin += 1 << (level - 2);
+        self.generation += 1 << j;
+        self.shrink();
+    }
+
+    pub fn advance(&mut self, generations: u64) {
+        (0..u64::BITS as u8)
+            .filter(|j| generations & (1 << j) != 0)
+            .for_each(|j| self.step_pow2(j));
+    }
+
+    pub fn population(&self) -> u128 {
+        self.population[self.root as usize]
+    }
+
+    /// Sum of the positions of all live cells.
+    pub fn position_sum(&self) -> i128 {
+        self.origin as i128 * self.population() as i128
+            + self.offset_sum[self.root as usize] as i128
+    }
+
+    /// Materialises the current row, which may be very wide.
+    pub fn to_row(&self) -> BitVecRow {
+        let mut positions = vec![];
+        self.collect(self.root, self.origin, &mut positions);
+        positions.into_iter().collect()
+    }
+
+    fn collect(&self, id: NodeId, start: i64, positions: &mut Vec<i64>) {
+        if self.is_empty(id) {
+            return;
+        }
+        match self.nodes[id as usize] {
+            Node::Leaf(bits) => {
+                positions.extend((0..4).filter(|i| bits & (1 << i) != 0).map(|i| start + i));
+            }
+            Node::Branch { level, left, right } => {
+                self.collect(left, start, positions);
+                self.collect(right, start + (1 << (level - 1)), positions);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    /// Steps a row one cell at a time, straight from the rule table.
+    fn naive_step(rule: &Rule1D, row: &BTreeSet<i64>) -> BTreeSet<i64> {
+        let (Some(&first), Some(&last)) = (row.first(), row.last()) else {
+            return BTreeSet::new();
+        };
+        (first - 2..=last + 2)
+            .filter(|&pos| {
+                let window =
+                    (pos - 2..=pos + 2).fold(0, |w, p| (w << 1) | usize::from(row.contains(&p)));
+                rule.table()[window]
+            })
+            .collect()
+    }
+
+    fn random_case(seed: u64) -> (Rule1D, BTreeSet<i64>) {
+        let mut rng = fastrand::Rng::with_seed(seed);
+        let mut table: [bool; 32] = std::array::from_fn(|_| rng.bool());
+        table[0] = false;
+        let width = rng.i64(1..=150);
+        let offset = rng.i64(-100..100);
+        let row = (0..width)
+            .filter(|_| rng.u8(..3) == 0)
+            .map(|i| offset + i)
+            .collect();
+        (Rule1D::new(table), row)
+    }
+
+    #[test]
+    fn test_rule_next_matches_naive() {
+        for seed in 0..200 {
+            let (rule, mut expected) = random_case(seed);
+            let mut row: BitVecRow = expected.iter().copied().collect();
+            for generation in 0..30 {
+                assert_eq!(
+                    row.iter().collect::<BTreeSet<_>>(),
+                    expected,
+                    "seed {seed}, generation {generation}"
+                );
+                row = rule.next(&row);
+                expected = naive_step(&rule, &expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hashlife_matches_naive() {
+        for seed in 0..200 {
+            let (rule, start) = random_case(seed);
+            let mut rng = fastrand::Rng::with_seed(seed + 1000);
+            let mut hl = HashLife1D::new(&start.iter().copied().collect(), rule);
+            let mut expected = start;
+            let mut generation = 0;
+            while generation < 200 {
+                let generations = rng.u64(..40);
+                hl.advance(generations);
+                for _ in 0..generations {
+                    expected = naive_step(&rule, &expected);
+                }
+                generation += generations;
+
+                let context = format!("seed {seed}, generation {generation}");
+                assert_eq!(hl.generation, generation, "{context}");
+                assert_eq!(
+                    hl.to_row().iter().collect::<BTreeSet<_>>(),
+                    expected,
+                    "{context}"
+                );
+                assert_eq!(hl.population(), expected.len() as u128, "{context}");
+                assert_eq!(
+                    hl.position_sum(),
+                    expected.iter().map(|&p| p as i128).sum(),
+                    "{context}"
+                );
+            }
+        }
+    }
+}
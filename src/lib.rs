@@ -1,6 +1,10 @@
 pub use clap::Parser;
+pub mod automaton;
 pub mod bitvec_set;
+pub mod cycle;
+pub mod geometry;
 pub mod grid_util;
+pub mod hashlife;
 pub mod vm;
 
 #[derive(Parser)]